@@ -47,7 +47,7 @@ pub fn parse_rotations(contents: &str) -> Vec<Rotation> {
         .filter_map(|raw_rotation| {
             let mut chars = raw_rotation.chars();
 
-            let direction = match chars.nth(0) {
+            let direction = match chars.next() {
                 Some('L') => Direction::Left,
                 Some('R') => Direction::Right,
                 _ => return None,
@@ -110,7 +110,7 @@ pub fn get_number_of_passes_through_zero(rotations: &Vec<Rotation>) -> i64 {
         };
     }
 
-    return zero_passes;
+    zero_passes
 }
 
 pub fn get_ends_at_zero(rotations: &Vec<Rotation>) -> i64 {
@@ -140,7 +140,7 @@ pub fn get_ends_at_zero(rotations: &Vec<Rotation>) -> i64 {
         }
     }
 
-    return zero_passes;
+    zero_passes
 }
 
 #[cfg(test)]
@@ -1,7 +1,7 @@
 //! Problem input
-//! 	11-22,95-115,998-1012,1188511880-1188511890,222220-222224,
-//! 	1698522-1698528,446443-446449,38593856-38593862,565653-565659,
-//! 	824824821-824824827,2121212118-2121212124
+//!     11-22,95-115,998-1012,1188511880-1188511890,222220-222224,
+//!     1698522-1698528,446443-446449,38593856-38593862,565653-565659,
+//!     824824821-824824827,2121212118-2121212124
 //!
 //!
 //! Shape
@@ -29,10 +29,9 @@ pub fn read_ranges(path: &Path) -> anyhow::Result<Vec<Range<u64>>> {
     let ranges: anyhow::Result<Vec<Range<u64>>> = ranges
         .trim()
         .split(",")
-        .into_iter()
         .map(|range| {
             let range = range.split("-").collect::<Vec<&str>>();
-            match (range.get(0), range.get(1)) {
+            match (range.first(), range.get(1)) {
                 (Some(start), Some(end)) => Ok(Range {
                     start: start.parse::<u64>()?,
                     end: end.parse::<u64>()?,
@@ -40,17 +39,8 @@ pub fn read_ranges(path: &Path) -> anyhow::Result<Vec<Range<u64>>> {
                 _ => bail!("Invalid range!"),
             }
         })
-        .fold(Ok(Vec::new()), |acc, range| {
-            let mut vec = match acc {
-                Ok(vec) => vec,
-                other => return other,
-            };
-
-            match range {
-                Ok(range) => vec.push(range),
-                Err(error) => return Err(error),
-            }
-
+        .try_fold(Vec::new(), |mut vec, range| {
+            vec.push(range?);
             Ok(vec)
         });
 
@@ -65,7 +55,7 @@ pub fn is_repeating_sequence(number: &str) -> bool {
     let mut window_sizes: Vec<usize> = Vec::new();
     let max_window_size = number.len() / 2;
     for window_size in 1..(max_window_size + 1) {
-        if number.len() % window_size != 0 {
+        if !number.len().is_multiple_of(window_size) {
             // The window doesn't fit neatly into the number
             continue;
         }
@@ -73,11 +63,9 @@ pub fn is_repeating_sequence(number: &str) -> bool {
         window_sizes.push(window_size);
     }
 
-    let is_repeating_sequence = window_sizes
+    window_sizes
         .iter()
-        .any(|window_size| is_repeating_sequence_for_window(&number, *window_size));
-
-    return is_repeating_sequence;
+        .any(|window_size| is_repeating_sequence_for_window(number, *window_size))
 }
 
 fn is_repeating_sequence_for_window(number: &str, window_size: usize) -> bool {
@@ -99,11 +87,11 @@ fn is_repeating_sequence_for_window(number: &str, window_size: usize) -> bool {
         }
     }
 
-    return true;
+    true
 }
 
 pub fn is_sequence_twice(number: &str) -> bool {
-    if number.len() % 2 != 0 {
+    if !number.len().is_multiple_of(2) {
         return false;
     }
     let number = number.chars().collect::<Vec<char>>();
@@ -116,15 +104,14 @@ pub fn is_sequence_twice(number: &str) -> bool {
                     return false;
                 }
             }
-            return true;
+            true
         }
-        _ => return false,
+        _ => false,
     }
 }
 
 pub fn find_invalid_ids(range: &Range<u64>, is_invalid_id: fn(&str) -> bool) -> u64 {
     (range.start..(range.end + 1))
-        .into_iter()
         .map(|number| (number, number.to_string()))
         .filter(|(_, number)| is_invalid_id(number))
         .fold(0, |acc, (number, _)| acc + number)
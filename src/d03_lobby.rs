@@ -1,7 +1,7 @@
 //! Notes:
 //! - Turn on exactly two batteries
 //! - The joltage they produce = the number formed by the digits of the batteries turned on
-//! 	- In 12345 if I turn on 2 and 4 I get 24
+//!     - In 12345 if I turn on 2 and 4 I get 24
 //! - Batteries cannot be re-arranged
 //! - Find max possible joltage
 //! - Sum all of the joltages
@@ -10,85 +10,633 @@
 //! Brute force -- double for-loop
 //! Dynamic programming?
 //! max_j(i + max_j(rest), max_j(rest))
+//!
+//! Monotonic stack -- pop smaller digits while enough batteries are left, O(n)
+//! Run with `--solver table` / `--solver stack` to compare timings
 
 use std::{
+    cmp::Ordering,
+    fmt,
     fs::File,
     io::{BufRead, BufReader},
+    iter::Sum,
+    ops::{Add, AddAssign},
     path::Path,
 };
 
-pub fn find_max_joltage(batteries: &[u8], activations: usize) -> u64 {
-    assert!(batteries.len() > 0, "There must be at least one battery");
+use anyhow::{Context, bail};
+
+/// Symbols a pack is written with, ordered by the digit value they stand for.
+/// The radix is the number of symbols.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigitAlphabet {
+    symbols: Vec<char>,
+}
+
+impl DigitAlphabet {
+    pub fn new(symbols: &str) -> anyhow::Result<Self> {
+        let symbols = symbols.chars().collect::<Vec<char>>();
+
+        if symbols.len() < 2 {
+            bail!("An alphabet needs at least two symbols");
+        }
+
+        if symbols.len() > u8::MAX as usize + 1 {
+            bail!(
+                "An alphabet can have at most {} symbols",
+                u8::MAX as usize + 1
+            );
+        }
+
+        for (i, symbol) in symbols.iter().enumerate() {
+            if symbols[..i].contains(symbol) {
+                bail!("Symbol '{}' appears more than once in the alphabet", symbol);
+            }
+        }
+
+        Ok(DigitAlphabet { symbols })
+    }
+
+    pub fn decimal() -> Self {
+        DigitAlphabet {
+            symbols: "0123456789".chars().collect(),
+        }
+    }
 
-    let mut max_joltages: Vec<Vec<u64>> = vec![vec![0; batteries.len()]; activations];
+    pub fn radix(&self) -> u32 {
+        self.symbols.len() as u32
+    }
 
-    for row in 0..activations {
-        for (col, battery) in batteries.iter().enumerate().rev() {
-            // Previous contains biggest possible (n-1) number
+    pub fn digit(&self, symbol: char) -> Option<u8> {
+        self.symbols
+            .iter()
+            .position(|candidate| *candidate == symbol)
+            .map(|digit| digit as u8)
+    }
+
+    pub fn symbol(&self, digit: u8) -> Option<char> {
+        self.symbols.get(digit as usize).copied()
+    }
+
+    pub fn parse(&self, line: &str) -> anyhow::Result<Vec<u8>> {
+        line.chars()
+            .map(|symbol| match self.digit(symbol) {
+                Some(digit) => Ok(digit),
+                None => bail!("Symbol '{}' is not part of the alphabet", symbol),
+            })
+            .collect()
+    }
+}
+
+impl Default for DigitAlphabet {
+    fn default() -> Self {
+        DigitAlphabet::decimal()
+    }
+}
+
+/// Arbitrary-precision joltage, so picking more than 19 batteries (or summing
+/// a lot of packs) doesn't overflow. Stored as little-endian base 2^32 limbs
+/// without trailing zero limbs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Joltage {
+    limbs: Vec<u32>,
+}
+
+impl Joltage {
+    pub fn from_digits(digits: impl IntoIterator<Item = u8>, radix: u32) -> Self {
+        let mut joltage = Joltage::default();
+        for digit in digits {
+            joltage.mul_add(radix, digit as u32);
+        }
+
+        joltage
+    }
+
+    fn mul_add(&mut self, mul: u32, add: u32) {
+        let mut carry = add as u64;
+        for limb in self.limbs.iter_mut() {
+            let value = (*limb as u64) * (mul as u64) + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    fn div_rem(&mut self, div: u32) -> u32 {
+        let mut rem: u64 = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (rem << 32) | *limb as u64;
+            *limb = (value / div as u64) as u32;
+            rem = value % div as u64;
+        }
+
+        self.trim();
+        rem as u32
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+}
+
+impl From<u64> for Joltage {
+    fn from(value: u64) -> Self {
+        let mut joltage = Joltage {
+            limbs: vec![value as u32, (value >> 32) as u32],
+        };
+        joltage.trim();
+        joltage
+    }
+}
+
+impl AddAssign<&Joltage> for Joltage {
+    fn add_assign(&mut self, other: &Joltage) {
+        if self.limbs.len() < other.limbs.len() {
+            self.limbs.resize(other.limbs.len(), 0);
+        }
+
+        let mut carry = 0u64;
+        for (i, limb) in self.limbs.iter_mut().enumerate() {
+            let value = *limb as u64 + *other.limbs.get(i).unwrap_or(&0) as u64 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+}
+
+impl Add for Joltage {
+    type Output = Joltage;
+
+    fn add(mut self, other: Joltage) -> Joltage {
+        self += &other;
+        self
+    }
+}
+
+impl Sum for Joltage {
+    fn sum<I: Iterator<Item = Joltage>>(iter: I) -> Self {
+        iter.fold(Joltage::default(), |acc, joltage| acc + joltage)
+    }
+}
+
+impl Ord for Joltage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for Joltage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Joltage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off 9 decimal digits at a time, least significant first
+        let mut rest = self.clone();
+        let mut chunks = vec![rest.div_rem(1_000_000_000)];
+        while !rest.limbs.is_empty() {
+            chunks.push(rest.div_rem(1_000_000_000));
+        }
+
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Digits picked at `indices` out of a sequence, keeping their original order,
+/// and the value they form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subsequence {
+    pub value: Joltage,
+    pub indices: Vec<usize>,
+}
+
+pub fn find_max_subsequence(digits: &[u8], length: usize, radix: u32) -> Subsequence {
+    assert!(!digits.is_empty(), "There must be at least one digit");
+    assert!(length > 0, "At least one digit must be picked");
+    assert!(
+        length <= digits.len(),
+        "Can't pick more digits than there are"
+    );
+    assert!(radix >= 2, "Radix must be at least 2");
+    assert!(
+        digits.iter().all(|digit| (*digit as u32) < radix),
+        "Every digit must be smaller than the radix"
+    );
+
+    assert!(
+        (radix as u64).checked_pow(length as u32).is_some(),
+        "The table solver only handles joltages that fit in a u64, use the stack solver"
+    );
+
+    let radix = radix as u64;
+    // max_values[row][col] is the biggest (row + 1)-digit number that can be
+    // picked from digits[col..], or None when there aren't enough digits left.
+    // The length is tracked by the row, never derived from the value, so
+    // zeros keep their place (05 is two digits even though it equals 5)
+    let mut max_values: Vec<Vec<Option<u64>>> = vec![vec![None; digits.len()]; length];
+    // Whether the best value for (row, col) starts with the digit at col
+    let mut taken: Vec<Vec<bool>> = vec![vec![false; digits.len()]; length];
+
+    for row in 0..length {
+        for (col, digit) in digits.iter().enumerate().rev() {
+            // Previous contains biggest possible row-digit number
             let prev = match (row, col) {
-                (0, _) => 0,
-                (_, j) if j == batteries.len() - 1 => 0,
-                _ => max_joltages[row - 1][col + 1],
+                (0, _) => Some(0),
+                (_, j) if j == digits.len() - 1 => None,
+                _ => max_values[row - 1][col + 1],
             };
 
-            // Right contains current biggest possible n number
+            // Right contains current biggest possible (row + 1)-digit number
             let right = match col {
-                j if j == batteries.len() - 1 => 0,
-                _ => max_joltages[row][col + 1],
+                j if j == digits.len() - 1 => None,
+                _ => max_values[row][col + 1],
             };
 
-            let exponent: u32 = if prev == 0 { 0 } else { prev.ilog10() + 1 };
-            let current = (*battery as u64) * 10_u64.pow(exponent) + prev;
-            max_joltages[row][col] = current.max(right);
+            let current = prev.map(|prev| (*digit as u64) * radix.pow(row as u32) + prev);
+            taken[row][col] = current.is_some() && current >= right;
+            max_values[row][col] = current.max(right);
+        }
+    }
+
+    let mut indices = Vec::with_capacity(length);
+    let mut row = length - 1;
+    let mut col = 0;
+    while col < digits.len() {
+        if taken[row][col] {
+            indices.push(col);
+            if row == 0 {
+                break;
+            }
+            row -= 1;
+        }
+
+        col += 1;
+    }
+
+    Subsequence {
+        value: Joltage::from(max_values[length - 1][0].expect("length fits in digits")),
+        indices,
+    }
+}
+
+/// Same result as `find_max_subsequence`, found greedily in a single pass.
+/// A digit is dropped from the stack whenever a bigger one comes after it
+/// and there are still enough digits left to fill `length`, so only O(length)
+/// memory is used.
+pub fn find_max_subsequence_stack(digits: &[u8], length: usize, radix: u32) -> Subsequence {
+    assert!(!digits.is_empty(), "There must be at least one digit");
+    assert!(length > 0, "At least one digit must be picked");
+    assert!(
+        length <= digits.len(),
+        "Can't pick more digits than there are"
+    );
+    assert!(radix >= 2, "Radix must be at least 2");
+    assert!(
+        digits.iter().all(|digit| (*digit as u32) < radix),
+        "Every digit must be smaller than the radix"
+    );
+
+    let mut indices: Vec<usize> = Vec::with_capacity(length);
+
+    for (col, digit) in digits.iter().enumerate() {
+        let remaining = digits.len() - col;
+
+        while let Some(&top) = indices.last() {
+            if digits[top] >= *digit || indices.len() - 1 + remaining < length {
+                break;
+            }
+            indices.pop();
+        }
+
+        if indices.len() < length {
+            indices.push(col);
         }
     }
 
-    return max_joltages[activations - 1][0];
+    let value = Joltage::from_digits(indices.iter().map(|i| digits[*i]), radix);
+
+    Subsequence { value, indices }
 }
 
 pub fn find_joltage_in_battery_packs(
-    packs: &Vec<Vec<u8>>,
-    find_max_joltage: fn(&[u8]) -> u64,
-) -> u64 {
-    assert!(packs.len() > 0, "There must be at least one pack");
+    packs: &[Vec<u8>],
+    find_max_joltage: impl Fn(&[u8]) -> anyhow::Result<Joltage>,
+) -> anyhow::Result<Joltage> {
+    if packs.is_empty() {
+        bail!("There must be at least one pack");
+    }
 
     packs
         .iter()
-        .map(|batteries| find_max_joltage(batteries))
-        .reduce(|acc, joltage| acc + joltage)
-        .expect("No joltage was found")
+        .enumerate()
+        .map(|(i, batteries)| {
+            find_max_joltage(batteries).with_context(|| format!("Pack {}", i + 1))
+        })
+        .sum()
 }
 
-pub fn read_battery_packs(path: &Path) -> Vec<Vec<u8>> {
-    let file = File::open(path).expect("Couldn't open file");
-    let reader = BufReader::new(file);
+/// Extra rules on which batteries can be activated together
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Constraints {
+    /// Batteries that have to stay off between two activated ones
+    pub min_gap: usize,
+    /// Index of the battery that must be the first one activated
+    pub first_battery: Option<usize>,
+}
 
-    let packs = reader
-        .lines()
-        .map(|pack| {
-            let pack = pack.expect("Is this the actual battery file?");
-            let pack = pack
-                .trim()
-                .split("")
-                .filter(|cell| !cell.is_empty())
-                .map(|battery| {
-                    battery
-                        .parse::<u8>()
-                        .expect("Cells should only contain numbers")
-                })
-                .collect::<Vec<u8>>();
+/// Exact maximum under `constraints`, or None when no choice of batteries
+/// satisfies them.
+///
+/// All candidates have the same length, so the biggest one is the one with
+/// the biggest first digit, then second digit, and so on. Each pick takes the
+/// biggest digit that still leaves room for the remaining picks, and the
+/// leftmost of them when there are ties since that leaves the most room.
+pub fn find_max_subsequence_constrained(
+    digits: &[u8],
+    length: usize,
+    radix: u32,
+    constraints: &Constraints,
+) -> Option<Subsequence> {
+    assert!(length > 0, "At least one digit must be picked");
+    assert!(radix >= 2, "Radix must be at least 2");
+    assert!(
+        digits.iter().all(|digit| (*digit as u32) < radix),
+        "Every digit must be smaller than the radix"
+    );
 
-            pack
-        })
-        .filter(|pack| pack.len() > 0)
-        .collect::<Vec<Vec<u8>>>();
+    if digits.is_empty() {
+        return None;
+    }
+
+    let step = constraints.min_gap + 1;
+    // Last position the pick number `pick` can take and still fit the rest
+    let last_position = |pick: usize| -> Option<usize> {
+        let needed = (length - pick - 1).checked_mul(step)?;
+        (digits.len() - 1).checked_sub(needed)
+    };
+
+    let mut indices: Vec<usize> = Vec::with_capacity(length);
+    let mut start = 0;
+
+    for pick in 0..length {
+        let end = last_position(pick)?;
+
+        let chosen = match (pick, constraints.first_battery) {
+            (0, Some(first)) if first > end => return None,
+            (0, Some(first)) => first,
+            _ if start > end => return None,
+            _ => (start..=end)
+                .rev()
+                .max_by_key(|i| digits[*i])
+                .expect("window is not empty"),
+        };
+
+        indices.push(chosen);
+        start = chosen + step;
+    }
+
+    let value = Joltage::from_digits(indices.iter().map(|i| digits[*i]), radix);
+
+    Some(Subsequence { value, indices })
+}
+
+/// Smallest value that can be picked, using a stack that pops bigger digits.
+/// With `leading_zero` off the first pick has to be a non-zero digit, and None
+/// is returned when there isn't one early enough in the sequence.
+pub fn find_min_subsequence(
+    digits: &[u8],
+    length: usize,
+    radix: u32,
+    leading_zero: bool,
+) -> Option<Subsequence> {
+    assert!(length > 0, "At least one digit must be picked");
+    assert!(
+        length <= digits.len(),
+        "Can't pick more digits than there are"
+    );
+    assert!(radix >= 2, "Radix must be at least 2");
+    assert!(
+        digits.iter().all(|digit| (*digit as u32) < radix),
+        "Every digit must be smaller than the radix"
+    );
+
+    let mut indices: Vec<usize> = Vec::with_capacity(length);
+    let mut start = 0;
+
+    if !leading_zero {
+        // Smallest non-zero digit that leaves room for the rest, leftmost on ties
+        let first = (0..=(digits.len() - length))
+            .filter(|i| digits[*i] != 0)
+            .min_by_key(|i| digits[*i])?;
+
+        indices.push(first);
+        start = first + 1;
+    }
+
+    // The forced first pick can't be popped
+    let fixed = indices.len();
 
+    for (col, digit) in digits.iter().enumerate().skip(start) {
+        let remaining = digits.len() - col;
+
+        while indices.len() > fixed {
+            let top = indices[indices.len() - 1];
+            if digits[top] <= *digit || indices.len() - 1 + remaining < length {
+                break;
+            }
+            indices.pop();
+        }
+
+        if indices.len() < length {
+            indices.push(col);
+        }
+    }
+
+    let value = Joltage::from_digits(indices.iter().map(|i| digits[*i]), radix);
+
+    Some(Subsequence { value, indices })
+}
+
+/// The `count` biggest distinct values that can be picked, biggest first.
+///
+/// Walks the candidates digit by digit, trying the biggest digit first and
+/// always taking its leftmost occurrence, so every distinct value is reached
+/// exactly once and in decreasing order.
+pub fn find_top_subsequences(
+    digits: &[u8],
+    length: usize,
+    radix: u32,
+    count: usize,
+) -> Vec<Subsequence> {
+    assert!(length > 0, "At least one digit must be picked");
+    assert!(
+        length <= digits.len(),
+        "Can't pick more digits than there are"
+    );
+    assert!(radix >= 2, "Radix must be at least 2");
+    assert!(
+        digits.iter().all(|digit| (*digit as u32) < radix),
+        "Every digit must be smaller than the radix"
+    );
+
+    // Sorted positions of every digit, to find the next occurrence quickly
+    let mut positions: Vec<Vec<usize>> = vec![Vec::new(); radix as usize];
+    for (i, digit) in digits.iter().enumerate() {
+        positions[*digit as usize].push(i);
+    }
+
+    let mut found = Vec::with_capacity(count);
+    let mut indices: Vec<usize> = Vec::with_capacity(length);
+    // One entry per digit picked so far plus the one being picked: every
+    // digit below it is still to be tried at that depth, biggest first.
+    // Kept by hand instead of recursing, since `length` can be huge
+    let mut untried: Vec<u32> = vec![radix];
+
+    while found.len() < count {
+        let Some(below) = untried.last_mut() else {
+            break;
+        };
+
+        if indices.len() == length {
+            found.push(Subsequence {
+                value: Joltage::from_digits(indices.iter().map(|i| digits[*i]), radix),
+                indices: indices.clone(),
+            });
+            untried.pop();
+            indices.pop();
+            continue;
+        }
+
+        let start = indices.last().map_or(0, |last| last + 1);
+        let end = digits.len() - (length - indices.len());
+
+        // Biggest digit left to try that still occurs where it leaves room
+        // for the rest, at its leftmost occurrence
+        let next = (0..*below).rev().find_map(|digit| {
+            let occurrences = &positions[digit as usize];
+            let next = occurrences.partition_point(|i| *i < start);
+            match occurrences.get(next) {
+                Some(i) if *i <= end => Some((digit, *i)),
+                _ => None,
+            }
+        });
+
+        match next {
+            Some((digit, i)) => {
+                *below = digit;
+                indices.push(i);
+                untried.push(radix);
+            }
+            None => {
+                untried.pop();
+                indices.pop();
+            }
+        }
+    }
+
+    found
+}
+
+/// Which batteries end up activated in every pack, along with the joltage each
+/// pack produces
+pub fn find_activations_in_battery_packs(
+    packs: &[Vec<u8>],
+    find_max_subsequence: impl Fn(&[u8]) -> anyhow::Result<Subsequence>,
+) -> anyhow::Result<Vec<Subsequence>> {
     packs
+        .iter()
+        .enumerate()
+        .map(|(i, batteries)| {
+            find_max_subsequence(batteries).with_context(|| format!("Pack {}", i + 1))
+        })
+        .collect()
+}
+
+const HIGHLIGHT: &str = "\x1b[1;32m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes the pack back out with its activated batteries highlighted,
+/// followed by the joltage they produce.
+pub fn render_activations(
+    batteries: &[u8],
+    activated: &Subsequence,
+    alphabet: &DigitAlphabet,
+) -> String {
+    let mut rendered = String::new();
+    let mut activated_indices = activated.indices.iter().peekable();
+
+    for (i, battery) in batteries.iter().enumerate() {
+        let symbol = alphabet
+            .symbol(*battery)
+            .expect("Batteries must be part of the alphabet");
+
+        if activated_indices.next_if_eq(&&i).is_some() {
+            rendered.push_str(HIGHLIGHT);
+            rendered.push(symbol);
+            rendered.push_str(RESET);
+        } else {
+            rendered.push(symbol);
+        }
+    }
+
+    format!("{} -> {}", rendered, activated.value)
+}
+
+pub fn read_battery_packs(path: &Path, alphabet: &DigitAlphabet) -> anyhow::Result<Vec<Vec<u8>>> {
+    let file = File::open(path).context("Couldn't open file")?;
+    let reader = BufReader::new(file);
+
+    let mut packs = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.context("Is this the actual battery file?")?;
+        let pack = alphabet
+            .parse(line.trim())
+            .with_context(|| format!("Invalid battery pack on line {}", number + 1))?;
+
+        if !pack.is_empty() {
+            packs.push(pack);
+        }
+    }
+
+    Ok(packs)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::d03_lobby::find_max_joltage;
+    use anyhow::Context;
+
+    use crate::{
+        d03_lobby::{
+            Constraints, DigitAlphabet, HIGHLIGHT, Joltage, RESET,
+            find_activations_in_battery_packs, find_joltage_in_battery_packs, find_max_subsequence,
+            find_max_subsequence_constrained, find_max_subsequence_stack, find_min_subsequence,
+            find_top_subsequences, render_activations,
+        },
+        xorshift::XorShift,
+    };
 
     #[test]
     fn test_find_max_joltage_valid() {
@@ -122,7 +670,8 @@ mod test {
         tests
             .into_iter()
             .for_each(|(batteries, activations, expect)| {
-                let result = find_max_joltage(&batteries, activations);
+                let expect = Joltage::from(expect);
+                let result = find_max_subsequence(&batteries, activations, 10).value;
                 assert_eq!(
                     expect, result,
                     "Expected battery to be {}, got {}. Battery pack: {:?}",
@@ -130,4 +679,268 @@ mod test {
                 );
             });
     }
+
+    #[test]
+    fn test_find_max_subsequence_other_radix() {
+        let alphabet = DigitAlphabet::new("0123456789abcdef").unwrap();
+        let digits = alphabet.parse("3f1a2c").unwrap();
+
+        let result = find_max_subsequence(&digits, 3, alphabet.radix());
+
+        assert_eq!(result.value, Joltage::from(0xfac));
+        assert_eq!(result.indices, vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn test_digit_alphabet_rejects_unknown_symbols() {
+        let alphabet = DigitAlphabet::new("ab").unwrap();
+
+        assert_eq!(alphabet.parse("abba").unwrap(), vec![0, 1, 1, 0]);
+        assert!(alphabet.parse("abc").is_err());
+        assert!(DigitAlphabet::new("aa").is_err());
+        assert!(DigitAlphabet::new("a").is_err());
+    }
+
+    #[test]
+    fn test_find_max_subsequence_stack_matches_table() {
+        let mut rng = XorShift::new(0x2545f4914f6cdd1d);
+
+        for _ in 0..500 {
+            let len = (rng.next_u64() % 19 + 1) as usize;
+            let batteries = (0..len)
+                .map(|_| (rng.next_u64() % 10) as u8)
+                .collect::<Vec<u8>>();
+            let activations = (rng.next_u64() % len as u64 + 1) as usize;
+
+            let table = find_max_subsequence(&batteries, activations, 10);
+            let stack = find_max_subsequence_stack(&batteries, activations, 10);
+
+            assert_eq!(
+                table, stack,
+                "Solvers disagree for {} activations of {:?}",
+                activations, batteries
+            );
+        }
+    }
+
+    #[test]
+    fn test_joltage_beyond_u64() {
+        let batteries = [9; 40];
+
+        let result = find_max_subsequence_stack(&batteries, 30, 10);
+        assert_eq!(result.value.to_string(), "9".repeat(30));
+        assert!(result.value > Joltage::from(u64::MAX));
+
+        let packs = vec![vec![9; 25], vec![1; 25]];
+        let total = find_joltage_in_battery_packs(&packs, |batteries| {
+            Ok(find_max_subsequence_stack(batteries, 25, 10).value)
+        })
+        .unwrap();
+        assert_eq!(total.to_string(), "11111111111111111111111110");
+        assert!(find_joltage_in_battery_packs(&[], |_| Ok(Joltage::from(0))).is_err());
+    }
+
+    #[test]
+    fn test_find_max_subsequence_with_zeros() {
+        let tests: [(&[u8], usize, u64, &[usize]); 9] = [
+            (&[0, 5], 2, 5, &[0, 1]),
+            (&[5, 0], 2, 50, &[0, 1]),
+            (&[0, 0, 0], 2, 0, &[0, 1]),
+            (&[0], 1, 0, &[0]),
+            (&[1, 0, 0, 1], 2, 11, &[0, 3]),
+            (&[1, 0, 0, 1], 3, 101, &[0, 1, 3]),
+            (&[0, 9, 0, 9], 3, 909, &[1, 2, 3]),
+            (&[9, 0, 0, 0, 1], 4, 9001, &[0, 1, 2, 4]),
+            (&[0, 0, 1, 0, 0], 4, 100, &[0, 2, 3, 4]),
+        ];
+
+        for (batteries, activations, expect, indices) in tests {
+            for result in [
+                find_max_subsequence(batteries, activations, 10),
+                find_max_subsequence_stack(batteries, activations, 10),
+            ] {
+                assert_eq!(
+                    result.value,
+                    Joltage::from(expect),
+                    "Wrong joltage for {} activations of {:?}",
+                    activations,
+                    batteries
+                );
+                assert_eq!(result.indices, indices);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_activations() {
+        let alphabet = DigitAlphabet::decimal();
+        let packs = vec![vec![8, 1, 8, 1, 9], vec![1, 2]];
+
+        let activated = find_activations_in_battery_packs(&packs, |batteries| {
+            Ok(find_max_subsequence_stack(batteries, 2, alphabet.radix()))
+        })
+        .unwrap();
+
+        assert_eq!(activated[0].indices, vec![0, 4]);
+        assert_eq!(activated[1].indices, vec![0, 1]);
+        assert_eq!(
+            render_activations(&packs[0], &activated[0], &alphabet),
+            format!("{HIGHLIGHT}8{RESET}181{HIGHLIGHT}9{RESET} -> 89")
+        );
+
+        let packs = vec![vec![1, 2], vec![0, 0]];
+        let error = find_activations_in_battery_packs(&packs, |batteries| {
+            find_min_subsequence(batteries, 2, 10, false).context("No non-zero battery")
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Pack 2");
+    }
+
+    #[test]
+    fn test_find_max_subsequence_constrained() {
+        let batteries = [9, 9, 1, 8, 2, 7];
+        let constraints = Constraints {
+            min_gap: 1,
+            first_battery: None,
+        };
+
+        let result = find_max_subsequence_constrained(&batteries, 3, 10, &constraints).unwrap();
+        assert_eq!(result.value, Joltage::from(987));
+        assert_eq!(result.indices, vec![0, 3, 5]);
+
+        let constraints = Constraints {
+            min_gap: 0,
+            first_battery: Some(2),
+        };
+        let result = find_max_subsequence_constrained(&batteries, 2, 10, &constraints).unwrap();
+        assert_eq!(result.value, Joltage::from(18));
+
+        let constraints = Constraints {
+            min_gap: 2,
+            first_battery: Some(1),
+        };
+        assert_eq!(
+            find_max_subsequence_constrained(&batteries, 3, 10, &constraints),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_max_subsequence_constrained_matches_brute_force() {
+        let mut rng = XorShift::new(0x9e3779b97f4a7c15);
+
+        for _ in 0..300 {
+            let len = (rng.next_u64() % 10 + 1) as usize;
+            let batteries = (0..len)
+                .map(|_| (rng.next_u64() % 10) as u8)
+                .collect::<Vec<u8>>();
+            let activations = (rng.next_u64() % len as u64 + 1) as usize;
+            let constraints = Constraints {
+                min_gap: (rng.next_u64() % 3) as usize,
+                first_battery: match rng.next_u64() % 2 {
+                    0 => None,
+                    _ => Some((rng.next_u64() % len as u64) as usize),
+                },
+            };
+
+            // Every subset of the right size, in order, smallest bitmask first
+            let expect = (0u32..(1 << len))
+                .filter(|mask| mask.count_ones() as usize == activations)
+                .map(|mask| {
+                    (0..len)
+                        .filter(|i| mask & (1 << i) != 0)
+                        .collect::<Vec<usize>>()
+                })
+                .filter(|indices| {
+                    indices
+                        .windows(2)
+                        .all(|pair| pair[1] - pair[0] > constraints.min_gap)
+                        && constraints
+                            .first_battery
+                            .is_none_or(|first| indices[0] == first)
+                })
+                .map(|indices| {
+                    indices
+                        .iter()
+                        .fold(0u64, |acc, i| acc * 10 + batteries[*i] as u64)
+                })
+                .max();
+
+            let result =
+                find_max_subsequence_constrained(&batteries, activations, 10, &constraints);
+
+            assert_eq!(
+                result.map(|subsequence| subsequence.value),
+                expect.map(Joltage::from),
+                "Wrong joltage for {} activations of {:?} with {:?}",
+                activations,
+                batteries,
+                constraints
+            );
+        }
+
+        let batteries = [3, 0, 9, 9, 4, 0, 1];
+        for activations in 1..=batteries.len() {
+            assert_eq!(
+                find_max_subsequence_constrained(
+                    &batteries,
+                    activations,
+                    10,
+                    &Constraints::default()
+                ),
+                Some(find_max_subsequence_stack(&batteries, activations, 10))
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_min_subsequence() {
+        let batteries = [3, 0, 9, 1, 0, 5];
+
+        let result = find_min_subsequence(&batteries, 3, 10, true).unwrap();
+        assert_eq!(result.value, Joltage::from(5));
+        assert_eq!(result.indices, vec![1, 4, 5]);
+
+        let result = find_min_subsequence(&batteries, 3, 10, false).unwrap();
+        assert_eq!(result.value, Joltage::from(105));
+        assert_eq!(result.indices, vec![3, 4, 5]);
+
+        assert_eq!(find_min_subsequence(&[0, 0, 7], 3, 10, false), None);
+        assert_eq!(
+            find_min_subsequence(&[0, 0, 7], 1, 10, false).map(|result| result.value),
+            Some(Joltage::from(7))
+        );
+    }
+
+    #[test]
+    fn test_find_top_subsequences() {
+        let batteries = [8, 1, 9, 1, 9];
+
+        let values = find_top_subsequences(&batteries, 2, 10, 5)
+            .into_iter()
+            .map(|subsequence| subsequence.value)
+            .collect::<Vec<Joltage>>();
+
+        assert_eq!(values, [99, 91, 89, 81, 19].map(Joltage::from).to_vec());
+
+        // 11 is the last one, there are only 6 distinct values
+        let all = find_top_subsequences(&batteries, 2, 10, 100);
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[5].value, Joltage::from(11));
+        assert_eq!(all[0], find_max_subsequence_stack(&batteries, 2, 10));
+    }
+
+    #[test]
+    fn test_find_top_subsequences_long() {
+        // Deep enough that one stack frame per picked digit would overflow.
+        // Mostly zeros keeps the joltages short enough to build quickly
+        let mut batteries = vec![0; 600_000];
+        batteries[599_999] = 1;
+
+        let top = find_top_subsequences(&batteries, 500_000, 2, 3);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0], find_max_subsequence_stack(&batteries, 500_000, 2));
+        assert_eq!(top[0].value, Joltage::from(1));
+        assert_eq!(top[1].indices, (0..500_000).collect::<Vec<usize>>());
+    }
 }
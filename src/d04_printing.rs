@@ -12,12 +12,28 @@
 //! - Adjacent is corners
 //!
 
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    hash::Hash,
+    io::Read,
+    mem,
+    ops::{Index, IndexMut},
+    path::Path,
+    str::FromStr,
+};
 
-#[derive(Clone, PartialEq, Debug)]
+use anyhow::{Context, bail};
+
+use crate::grid::{Grid, OFFSETS_8};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Cell {
     Empty,
     Roll,
+    /// Still a roll, just marked to be removed. Every backend counts and
+    /// removes it like any other roll, even when it comes from the input
     ToRemove,
 }
 
@@ -31,138 +47,1091 @@ impl From<Cell> for i32 {
     }
 }
 
-fn access_grid_with_bounds(grid: &Vec<Vec<Cell>>, i: i32, j: i32) -> Cell {
-    match (i, j) {
-        (i, _) if i < 0 => Cell::Empty,
-        (i, _) if (i as usize) >= grid.len() => Cell::Empty,
-        (_, j) if j < 0 => Cell::Empty,
-        (_, j) if (j as usize) >= grid[i as usize].len() => Cell::Empty,
-        _ => grid[i as usize][j as usize].clone(),
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Empty => write!(f, "."),
+            Cell::Roll => write!(f, "@"),
+            Cell::ToRemove => write!(f, "x"),
+        }
+    }
+}
+
+/// Which cells around a roll count as its neighbors
+#[derive(Debug, Clone, PartialEq)]
+pub enum Neighborhood {
+    /// Square of the given radius, corners included
+    Moore(usize),
+    /// Diamond of the given radius, reachable in that many up/down/left/right steps
+    VonNeumann(usize),
+    /// Explicit `(di, dj)` offsets
+    Custom(Vec<(isize, isize)>),
+}
+
+impl Neighborhood {
+    pub fn offsets(&self) -> Vec<(isize, isize)> {
+        let square = |radius: usize| {
+            let radius = radius as isize;
+            (-radius..=radius)
+                .flat_map(move |di| (-radius..=radius).map(move |dj| (di, dj)))
+                .filter(|offset| *offset != (0, 0))
+        };
+
+        match self {
+            Neighborhood::Moore(radius) => square(*radius).collect(),
+            Neighborhood::VonNeumann(radius) => square(*radius)
+                .filter(|(di, dj)| di.unsigned_abs() + dj.unsigned_abs() <= *radius)
+                .collect(),
+            Neighborhood::Custom(offsets) => offsets.clone(),
+        }
     }
 }
 
-fn is_removable(grid: &Vec<Vec<Cell>>, i: usize, j: usize) -> bool {
-    let row = i as i32;
-    let col = j as i32;
-    let mut acc = 0;
+/// `moore`, `moore:2`, `von-neumann:1` or `custom:-1,0;1,0`
+impl FromStr for Neighborhood {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let (kind, argument) = match value.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (value, None),
+        };
 
-    for i in -1..2 {
-        for j in -1..2 {
-            if j == 0 && i == 0 {
-                continue;
+        let radius = || -> anyhow::Result<usize> {
+            match argument {
+                Some(radius) => radius.parse().context("Radius must be a number"),
+                None => Ok(1),
             }
-            acc += i32::from(access_grid_with_bounds(grid, row + i, col + j))
+        };
+
+        match kind {
+            "moore" => Ok(Neighborhood::Moore(radius()?)),
+            "von-neumann" => Ok(Neighborhood::VonNeumann(radius()?)),
+            "custom" => {
+                let offsets = argument
+                    .context("Custom neighborhoods need their offsets, like custom:-1,0;1,0")?
+                    .split(';')
+                    .map(|offset| {
+                        let (di, dj) = offset
+                            .split_once(',')
+                            .with_context(|| format!("Offset '{}' must look like di,dj", offset))?;
+                        Ok((di.trim().parse()?, dj.trim().parse()?))
+                    })
+                    .collect::<anyhow::Result<Vec<(isize, isize)>>>()?;
+
+                Ok(Neighborhood::Custom(offsets))
+            }
+            other => bail!("Unknown neighborhood '{}'", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    pub fn holds(&self, count: usize, threshold: usize) -> bool {
+        match self {
+            Comparison::Less => count < threshold,
+            Comparison::LessOrEqual => count <= threshold,
+            Comparison::Equal => count == threshold,
+            Comparison::GreaterOrEqual => count >= threshold,
+            Comparison::Greater => count > threshold,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "<" => Ok(Comparison::Less),
+            "<=" => Ok(Comparison::LessOrEqual),
+            "=" | "==" => Ok(Comparison::Equal),
+            ">=" => Ok(Comparison::GreaterOrEqual),
+            ">" => Ok(Comparison::Greater),
+            other => bail!("Unknown comparison '{}', use one of <, <=, =, >=, >", other),
         }
     }
+}
 
-    return acc < 4;
+/// What the cells past the edge of the warehouse look like
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    Empty,
+    /// The opposite edge, like a torus
+    Wrap,
+    Roll,
 }
 
-pub fn get_accessable_rolls_with_removal(grid: &mut Vec<Vec<Cell>>) -> u32 {
-    let mut moved_rolls = access_and_remove_rolls(grid);
-    let mut new_moved_rolls = moved_rolls;
+impl FromStr for Boundary {
+    type Err = anyhow::Error;
 
-    while new_moved_rolls > 0 {
-        new_moved_rolls = access_and_remove_rolls(grid);
-        moved_rolls += new_moved_rolls;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "empty" => Ok(Boundary::Empty),
+            "wrap" => Ok(Boundary::Wrap),
+            "roll" => Ok(Boundary::Roll),
+            other => bail!("Unknown boundary '{}', use one of empty, wrap, roll", other),
+        }
     }
+}
 
-    moved_rolls
+/// A roll can be accessed when the number of rolls in its neighborhood
+/// compares to the threshold. The puzzle's rule is the default: fewer than 4
+/// rolls in the 8 adjacent positions, with nothing past the edges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub neighborhood: Neighborhood,
+    pub threshold: usize,
+    pub comparison: Comparison,
+    pub boundary: Boundary,
 }
 
-fn access_and_remove_rolls(grid: &mut Vec<Vec<Cell>>) -> u32 {
-    let moved_rolls = get_accessable_rolls(
-        grid,
-        Some(|grid: &mut Vec<Vec<Cell>>, row: usize, col: usize| {
-            grid[row][col] = Cell::ToRemove;
-        }),
+impl Default for Rule {
+    fn default() -> Self {
+        Rule {
+            neighborhood: Neighborhood::Moore(1),
+            threshold: 4,
+            comparison: Comparison::Less,
+            boundary: Boundary::Empty,
+        }
+    }
+}
+
+impl Rule {
+    /// Rolls among the cells `offsets` away from `position`. The offsets are
+    /// the rule's neighborhood, worked out once by the caller instead of per cell.
+    pub fn count_neighbors<W: Warehouse>(
+        &self,
+        warehouse: &W,
+        offsets: &[(isize, isize)],
+        position: W::Position,
+    ) -> usize {
+        let mut inside = 0;
+        let mut count = 0;
+        warehouse.for_each_neighbor(position, offsets, self.boundary, |neighbor| {
+            inside += 1;
+            count += i32::from(warehouse.cell(neighbor)) as usize;
+        });
+
+        match self.boundary {
+            // Every offset that falls outside is a roll
+            Boundary::Roll => count + offsets.len() - inside,
+            Boundary::Empty | Boundary::Wrap => count,
+        }
+    }
+
+    pub fn is_accessible<W: Warehouse>(
+        &self,
+        warehouse: &W,
+        offsets: &[(isize, isize)],
+        position: W::Position,
+    ) -> bool {
+        self.comparison.holds(
+            self.count_neighbors(warehouse, offsets, position),
+            self.threshold,
+        )
+    }
+}
+
+/// How a backend keeps the cells of a warehouse. Finding the accessible rolls,
+/// the removal rounds and the statistics only go through this, so they work
+/// the same on every backend that can hold any cell.
+pub trait Warehouse {
+    /// Where a cell is, like `(i, j)` on the dense grid
+    type Position: Copy + Ord + Hash + fmt::Debug;
+    /// One value for each position, like the neighbor count of every roll
+    type Map<V: Clone>: Index<Self::Position, Output = V> + IndexMut<Self::Position>;
+
+    /// Map with `fill` at every position
+    fn new_map<V: Clone>(&self, fill: V) -> Self::Map<V>;
+
+    /// Positions of the rolls, row by row, including the ones marked to be removed
+    fn roll_positions(&self) -> Vec<Self::Position>;
+
+    fn cell(&self, position: Self::Position) -> Cell;
+
+    fn set_cell(&mut self, position: Self::Position, cell: Cell);
+
+    /// Calls `visit` with every position `offsets` away from `position` that
+    /// is inside the warehouse, wrapping around the edges with `Boundary::Wrap`
+    fn for_each_neighbor(
+        &self,
+        position: Self::Position,
+        offsets: &[(isize, isize)],
+        boundary: Boundary,
+        visit: impl FnMut(Self::Position),
     );
 
-    map_grid(grid, &mut |grid: &mut Vec<Vec<Cell>>,
-                         row: usize,
-                         col: usize| {
-        if grid[row][col] == Cell::ToRemove {
-            grid[row][col] = Cell::Empty;
+    /// Errors when the warehouse can't have `boundary` around it
+    fn check_boundary(&self, boundary: Boundary) -> anyhow::Result<()>;
+
+    /// One character per cell, a line per row
+    fn render(&self, symbol: impl FnMut(Self::Position) -> char) -> String;
+}
+
+impl Warehouse for Grid<Cell> {
+    type Position = (usize, usize);
+    type Map<V: Clone> = Grid<V>;
+
+    fn new_map<V: Clone>(&self, fill: V) -> Grid<V> {
+        Grid::new(self.width(), self.height(), fill)
+    }
+
+    fn roll_positions(&self) -> Vec<(usize, usize)> {
+        self.positions()
+            .filter(|position| self[*position] != Cell::Empty)
+            .collect()
+    }
+
+    fn cell(&self, position: (usize, usize)) -> Cell {
+        self[position]
+    }
+
+    fn set_cell(&mut self, position: (usize, usize), cell: Cell) {
+        self[position] = cell;
+    }
+
+    fn for_each_neighbor(
+        &self,
+        (i, j): (usize, usize),
+        offsets: &[(isize, isize)],
+        boundary: Boundary,
+        visit: impl FnMut((usize, usize)),
+    ) {
+        match boundary {
+            Boundary::Wrap => self.neighbors_wrapping(i, j, offsets).for_each(visit),
+            Boundary::Empty | Boundary::Roll => self.neighbors(i, j, offsets).for_each(visit),
         }
-    });
+    }
+
+    fn check_boundary(&self, _: Boundary) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn render(&self, mut symbol: impl FnMut((usize, usize)) -> char) -> String {
+        let mut rendered = String::with_capacity((self.width() + 1) * self.height());
+        for (i, j) in self.positions() {
+            rendered.push(symbol((i, j)));
+            if j + 1 == self.width() {
+                rendered.push('\n');
+            }
+        }
+
+        rendered
+    }
+}
 
-    moved_rolls
+/// One wave of forklifts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Round<Position = (usize, usize)> {
+    /// Rolls taken away in this round
+    pub removed: Vec<Position>,
+    /// Rolls still standing after it
+    pub remaining: usize,
 }
 
-fn map_grid<Transform>(grid: &mut Vec<Vec<Cell>>, transform: &mut Transform)
+pub fn get_accessable_rolls_with_removal<W: Warehouse>(
+    warehouse: &mut W,
+    rule: &Rule,
+) -> anyhow::Result<u32> {
+    Ok(
+        get_removal_rounds::<W, fn(&W, &Round<W::Position>)>(warehouse, rule, None)?
+            .iter()
+            .map(|round| round.removed.len() as u32)
+            .sum(),
+    )
+}
+
+/// Keeps removing accessible rolls, in rounds, until none are left.
+///
+/// Instead of rescanning the whole warehouse every round, the neighbor count
+/// of every roll is kept up to date as rolls disappear, and only the rolls next
+/// to the ones just removed are checked again, since nothing changed for the
+/// rest. The rolls of a round are all removed at once, like the forklifts do.
+///
+/// `on_round` sees the warehouse with the round's rolls still marked as
+/// `Cell::ToRemove`, right before they are cleared.
+pub fn get_removal_rounds<W, OnRound>(
+    warehouse: &mut W,
+    rule: &Rule,
+    mut on_round: Option<OnRound>,
+) -> anyhow::Result<Vec<Round<W::Position>>>
 where
-    Transform: FnMut(&mut Vec<Vec<Cell>>, usize, usize),
+    W: Warehouse,
+    OnRound: FnMut(&W, &Round<W::Position>),
 {
-    for row in 0..grid.len() {
-        for col in 0..grid[row].len() {
-            transform(grid, row, col)
+    warehouse.check_boundary(rule.boundary)?;
+
+    let offsets = rule.neighborhood.offsets();
+    // Cells that have a position as a neighbor are the ones (di, dj) behind it
+    let behind = offsets
+        .iter()
+        .map(|(di, dj)| (-di, -dj))
+        .collect::<Vec<(isize, isize)>>();
+
+    let mut candidates = warehouse.roll_positions();
+    let mut counts = warehouse.new_map(0usize);
+    for position in candidates.iter() {
+        counts[*position] = rule.count_neighbors(warehouse, &offsets, *position);
+    }
+
+    // Round in which each roll was last queued, so it's only checked once per round
+    let mut queued_in = warehouse.new_map(0usize);
+    let mut remaining = candidates.len();
+    let mut rounds = Vec::new();
+
+    while !candidates.is_empty() {
+        let mut removed = candidates
+            .drain(..)
+            .filter(|position| {
+                warehouse.cell(*position) != Cell::Empty
+                    && rule.comparison.holds(counts[*position], rule.threshold)
+            })
+            .collect::<Vec<W::Position>>();
+        // Row by row, like the first round
+        removed.sort_unstable();
+
+        if removed.is_empty() {
+            break;
+        }
+
+        for position in removed.iter() {
+            warehouse.set_cell(*position, Cell::ToRemove);
+        }
+        remaining -= removed.len();
+
+        let round = Round { removed, remaining };
+        if let Some(on_round) = on_round.as_mut() {
+            on_round(warehouse, &round);
+        }
+
+        for position in round.removed.iter() {
+            warehouse.set_cell(*position, Cell::Empty);
+        }
+
+        // Only the counts of rolls still standing are ever read again
+        for position in round.removed.iter() {
+            warehouse.for_each_neighbor(*position, &behind, rule.boundary, |neighbor| {
+                if warehouse.cell(neighbor) == Cell::Empty {
+                    return;
+                }
+
+                counts[neighbor] -= 1;
+                if queued_in[neighbor] != rounds.len() + 1 {
+                    queued_in[neighbor] = rounds.len() + 1;
+                    candidates.push(neighbor);
+                }
+            });
+        }
+
+        rounds.push(round);
+    }
+
+    Ok(rounds)
+}
+
+/// Neighbor count of every roll under `rule`, None where there's no roll
+pub fn get_neighbor_counts<W: Warehouse>(
+    warehouse: &W,
+    rule: &Rule,
+) -> anyhow::Result<W::Map<Option<usize>>> {
+    warehouse.check_boundary(rule.boundary)?;
+
+    let offsets = rule.neighborhood.offsets();
+    let mut counts = warehouse.new_map(None);
+    for position in warehouse.roll_positions() {
+        counts[position] = Some(rule.count_neighbors(warehouse, &offsets, position));
+    }
+
+    Ok(counts)
+}
+
+/// Neighbor counts as digits, `+` for counts over 9 and `.` where there's no roll
+pub fn render_heatmap<W: Warehouse>(warehouse: &W, counts: &W::Map<Option<usize>>) -> String {
+    warehouse.render(|position| match counts[position] {
+        None => '.',
+        Some(count) if count > 9 => '+',
+        Some(count) => char::from_digit(count as u32, 10).expect("count is a digit"),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollStatistics<Depths> {
+    /// Number of rolls with each neighbor count, indexed by the count
+    pub histogram: Vec<usize>,
+    /// Rolls still standing once nothing else can be removed
+    pub never_accessible: usize,
+    /// Round (1-index) in which each roll is removed, None if it never is
+    pub depths: Depths,
+}
+
+pub fn get_roll_statistics<W: Warehouse + Clone>(
+    warehouse: &W,
+    rule: &Rule,
+) -> anyhow::Result<RollStatistics<W::Map<Option<usize>>>> {
+    let rolls = warehouse.roll_positions();
+    let counts = get_neighbor_counts(warehouse, rule)?;
+
+    let mut histogram = Vec::new();
+    for count in rolls.iter().filter_map(|position| counts[*position]) {
+        if histogram.len() <= count {
+            histogram.resize(count + 1, 0);
         }
+        histogram[count] += 1;
     }
+
+    let mut depths = warehouse.new_map(None);
+    let rounds =
+        get_removal_rounds::<W, fn(&W, &Round<W::Position>)>(&mut warehouse.clone(), rule, None)?;
+    for (depth, round) in rounds.iter().enumerate() {
+        for position in round.removed.iter() {
+            depths[*position] = Some(depth + 1);
+        }
+    }
+
+    let never_accessible = match rounds.last() {
+        Some(round) => round.remaining,
+        None => rolls.len(),
+    };
+
+    Ok(RollStatistics {
+        histogram,
+        never_accessible,
+        depths,
+    })
 }
 
-pub fn get_accessable_rolls<OnRemove>(
-    grid: &mut Vec<Vec<Cell>>,
+fn map_rolls<W, Transform>(warehouse: &mut W, transform: &mut Transform)
+where
+    W: Warehouse,
+    Transform: FnMut(&mut W, W::Position),
+{
+    for position in warehouse.roll_positions() {
+        transform(warehouse, position)
+    }
+}
+
+pub fn get_accessable_rolls<W, OnRemove>(
+    warehouse: &mut W,
+    rule: &Rule,
     mut on_remove: Option<OnRemove>,
-) -> u32
+) -> anyhow::Result<u32>
 where
-    OnRemove: FnMut(&mut Vec<Vec<Cell>>, usize, usize),
+    W: Warehouse,
+    OnRemove: FnMut(&mut W, W::Position),
 {
+    warehouse.check_boundary(rule.boundary)?;
+
+    let offsets = rule.neighborhood.offsets();
+    let mut moved_rolls = 0;
+
+    map_rolls(
+        warehouse,
+        &mut |warehouse: &mut W, position: W::Position| {
+            if warehouse.cell(position) == Cell::Empty {
+                return;
+            }
+
+            if rule.is_accessible(warehouse, &offsets, position) {
+                moved_rolls += 1;
+                if let Some(on_remove) = on_remove.as_mut() {
+                    on_remove(warehouse, position)
+                }
+            }
+        },
+    );
+
+    Ok(moved_rolls)
+}
+
+/// Rolls packed one bit per cell, 64 cells to a word, every row starting on
+/// a new word. Neighbor counts for a whole word of cells are worked out at
+/// once by shifting the rows around it and adding them up bit by bit, so
+/// huge warehouses fit in memory and each round is a few word operations
+/// per cell.
+///
+/// Only the puzzle's 8-cell neighborhood is supported, with either empty or
+/// roll boundaries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BitGrid {
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl BitGrid {
+    pub fn rolls(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    fn row(&self, i: usize) -> &[u64] {
+        &self.bits[i * self.words_per_row..(i + 1) * self.words_per_row]
+    }
+
+    /// Bits of the last word of a row that are real cells, not padding
+    fn last_word_mask(&self) -> u64 {
+        match self.width % 64 {
+            0 => u64::MAX,
+            used => (1 << used) - 1,
+        }
+    }
+
+    /// Rolls that are accessible under `rule`, as a mask over the grid
+    pub fn accessible(&self, rule: &Rule) -> anyhow::Result<BitGrid> {
+        // Same 8 offsets in any order count the same neighbors
+        let mut offsets = rule.neighborhood.offsets();
+        offsets.sort_unstable();
+        if offsets != OFFSETS_8 {
+            bail!("The bit grid only supports the moore neighborhood with radius 1");
+        }
+
+        // Value of every cell past the edges, spread over a whole word
+        let outside = match rule.boundary {
+            Boundary::Empty => 0,
+            Boundary::Roll => u64::MAX,
+            Boundary::Wrap => bail!("The bit grid doesn't support wrapping boundaries"),
+        };
+
+        let last_word_mask = self.last_word_mask();
+        // Copies a row into `buffer`, or the outside past the edges. Padding
+        // past the last column reads like the outside too
+        let load = |buffer: &mut [u64], row: Option<&[u64]>| match row {
+            None => buffer.fill(outside),
+            Some(row) => {
+                buffer.copy_from_slice(row);
+                if let Some(last) = buffer.last_mut() {
+                    *last = (*last & last_word_mask) | (outside & !last_word_mask);
+                }
+            }
+        };
+
+        let mut mask = BitGrid {
+            bits: vec![0; self.bits.len()],
+            ..*self
+        };
+
+        // Rows i - 1, i and i + 1, moved up a row at a time so only the one
+        // below is copied in
+        let mut above = vec![outside; self.words_per_row];
+        let mut current = vec![0; self.words_per_row];
+        let mut below = vec![0; self.words_per_row];
+        load(&mut current, (self.height > 0).then(|| self.row(0)));
+
+        for i in 0..self.height {
+            load(&mut below, (i + 1 < self.height).then(|| self.row(i + 1)));
+
+            for k in 0..self.words_per_row {
+                let neighbors = [
+                    west(&above, k, outside),
+                    above[k],
+                    east(&above, k, outside),
+                    west(&current, k, outside),
+                    east(&current, k, outside),
+                    west(&below, k, outside),
+                    below[k],
+                    east(&below, k, outside),
+                ];
+
+                // Four bit planes are enough to count up to 8 neighbors
+                let mut planes = [0u64; 4];
+                for neighbor in neighbors {
+                    let mut carry = neighbor;
+                    for plane in planes.iter_mut() {
+                        let sum = *plane ^ carry;
+                        carry &= *plane;
+                        *plane = sum;
+                    }
+                }
+
+                let valid = match k + 1 {
+                    next if next == self.words_per_row => last_word_mask,
+                    _ => u64::MAX,
+                };
+
+                mask.bits[i * self.words_per_row + k] =
+                    compare_planes(&planes, rule) & self.bits[i * self.words_per_row + k] & valid;
+            }
+
+            // The old row above gets overwritten by the next row below
+            mem::swap(&mut above, &mut current);
+            mem::swap(&mut current, &mut below);
+        }
+
+        Ok(mask)
+    }
+
+    pub fn remove(&mut self, mask: &BitGrid) {
+        for (word, removed) in self.bits.iter_mut().zip(mask.bits.iter()) {
+            *word &= !removed;
+        }
+    }
+}
+
+/// Bit j is set when cell j - 1 is a roll
+fn west(row: &[u64], k: usize, outside: u64) -> u64 {
+    let previous = match k {
+        0 => outside,
+        _ => row[k - 1],
+    };
+
+    (row[k] << 1) | (previous >> 63)
+}
+
+/// Bit j is set when cell j + 1 is a roll
+fn east(row: &[u64], k: usize, outside: u64) -> u64 {
+    let next = *row.get(k + 1).unwrap_or(&outside);
+
+    (row[k] >> 1) | (next << 63)
+}
+
+/// Compares the counts held in bit planes (least significant first) against
+/// the rule's threshold, for 64 cells at a time
+fn compare_planes(planes: &[u64; 4], rule: &Rule) -> u64 {
+    // Counts never go past 8, so any bigger threshold compares the same as 16
+    let threshold = rule.threshold.min(16);
+
+    let (less, equal) = match threshold {
+        16 => (u64::MAX, 0),
+        threshold => {
+            let mut less = 0;
+            let mut equal = u64::MAX;
+            for bit in (0..4).rev() {
+                if threshold & (1 << bit) != 0 {
+                    less |= equal & !planes[bit];
+                    equal &= planes[bit];
+                } else {
+                    equal &= !planes[bit];
+                }
+            }
+            (less, equal)
+        }
+    };
+
+    match rule.comparison {
+        Comparison::Less => less,
+        Comparison::LessOrEqual => less | equal,
+        Comparison::Equal => equal,
+        Comparison::GreaterOrEqual => !less,
+        Comparison::Greater => !(less | equal),
+    }
+}
+
+pub fn get_accessable_rolls_bits(grid: &BitGrid, rule: &Rule) -> anyhow::Result<u32> {
+    Ok(grid.accessible(rule)?.rolls() as u32)
+}
+
+pub fn get_accessable_rolls_with_removal_bits(
+    grid: &mut BitGrid,
+    rule: &Rule,
+) -> anyhow::Result<u32> {
     let mut moved_rolls = 0;
 
-    map_grid(grid, &mut |grid: &mut Vec<Vec<Cell>>,
-                         row: usize,
-                         col: usize| {
-        if grid[row][col] != Cell::Roll {
-            return;
+    loop {
+        let accessible = grid.accessible(rule)?;
+        let removed = accessible.rolls();
+        if removed == 0 {
+            return Ok(moved_rolls);
+        }
+
+        grid.remove(&accessible);
+        moved_rolls += removed as u32;
+    }
+}
+
+/// Only the cells that aren't empty, so mostly empty warehouses don't pay for
+/// their empty cells. Positions can be negative and, without bounds, the
+/// warehouse goes on forever in every direction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparseGrid {
+    cells: HashMap<(i64, i64), Cell>,
+    /// Height and width, for warehouses that have edges
+    bounds: Option<(i64, i64)>,
+}
+
+impl SparseGrid {
+    /// Same cells, with nothing but empty space around them
+    pub fn into_unbounded(self) -> Self {
+        SparseGrid {
+            bounds: None,
+            ..self
+        }
+    }
+}
+
+/// Values for the positions of a `SparseGrid`, only stored once they are set
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseMap<V> {
+    values: HashMap<(i64, i64), V>,
+    fill: V,
+}
+
+impl<V> Index<(i64, i64)> for SparseMap<V> {
+    type Output = V;
+
+    fn index(&self, position: (i64, i64)) -> &V {
+        self.values.get(&position).unwrap_or(&self.fill)
+    }
+}
+
+impl<V: Clone> IndexMut<(i64, i64)> for SparseMap<V> {
+    fn index_mut(&mut self, position: (i64, i64)) -> &mut V {
+        self.values
+            .entry(position)
+            .or_insert_with(|| self.fill.clone())
+    }
+}
+
+impl Warehouse for SparseGrid {
+    type Position = (i64, i64);
+    type Map<V: Clone> = SparseMap<V>;
+
+    fn new_map<V: Clone>(&self, fill: V) -> SparseMap<V> {
+        SparseMap {
+            values: HashMap::new(),
+            fill,
+        }
+    }
+
+    fn roll_positions(&self) -> Vec<(i64, i64)> {
+        let mut rolls = self
+            .cells
+            .iter()
+            .filter(|(_, cell)| **cell != Cell::Empty)
+            .map(|(position, _)| *position)
+            .collect::<Vec<(i64, i64)>>();
+        rolls.sort_unstable();
+
+        rolls
+    }
+
+    fn cell(&self, position: (i64, i64)) -> Cell {
+        self.cells.get(&position).copied().unwrap_or(Cell::Empty)
+    }
+
+    fn set_cell(&mut self, position: (i64, i64), cell: Cell) {
+        match cell {
+            Cell::Empty => self.cells.remove(&position),
+            cell => self.cells.insert(position, cell),
+        };
+    }
+
+    fn for_each_neighbor(
+        &self,
+        (i, j): (i64, i64),
+        offsets: &[(isize, isize)],
+        boundary: Boundary,
+        mut visit: impl FnMut((i64, i64)),
+    ) {
+        for (di, dj) in offsets.iter() {
+            let (i, j) = (i + *di as i64, j + *dj as i64);
+
+            match (self.bounds, boundary) {
+                (Some((height, width)), Boundary::Wrap) => {
+                    visit((i.rem_euclid(height), j.rem_euclid(width)))
+                }
+                (Some((height, width)), _) if i < 0 || j < 0 || i >= height || j >= width => (),
+                _ => visit((i, j)),
+            }
+        }
+    }
+
+    fn check_boundary(&self, boundary: Boundary) -> anyhow::Result<()> {
+        match (self.bounds, boundary) {
+            (None, Boundary::Wrap) => bail!("An unbounded warehouse has no edges to wrap around"),
+            (None, Boundary::Roll) => {
+                bail!("An unbounded warehouse has no edges to fill with rolls")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Everything inside the bounds or, without them, the smallest rectangle
+    /// around the cells that aren't empty
+    fn render(&self, mut symbol: impl FnMut((i64, i64)) -> char) -> String {
+        let (rows, columns) = match self.bounds {
+            Some((height, width)) => (0..height, 0..width),
+            None => {
+                let rows = self.cells.keys().map(|(i, _)| *i);
+                let columns = self.cells.keys().map(|(_, j)| *j);
+                match (
+                    rows.clone().min().zip(rows.max()),
+                    columns.clone().min().zip(columns.max()),
+                ) {
+                    (Some((top, bottom)), Some((left, right))) => {
+                        (top..bottom + 1, left..right + 1)
+                    }
+                    _ => (0..0, 0..0),
+                }
+            }
+        };
+
+        let mut rendered = String::new();
+        for i in rows {
+            for j in columns.clone() {
+                rendered.push(symbol((i, j)));
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+}
+
+impl fmt::Display for SparseGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&write_rolls(self, &Symbols::default()))
+    }
+}
+
+/// Characters each kind of cell can be written with. Any of them is
+/// accepted when reading, and the first one is used when writing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbols {
+    empty: Vec<char>,
+    roll: Vec<char>,
+    to_remove: Vec<char>,
+    /// Ignore whitespace around every line. Turn it off when spaces are cells
+    pub trim: bool,
+}
+
+impl Symbols {
+    pub fn new(empty: &str, roll: &str, to_remove: &str) -> anyhow::Result<Self> {
+        let symbols = Symbols {
+            empty: empty.chars().collect(),
+            roll: roll.chars().collect(),
+            to_remove: to_remove.chars().collect(),
+            trim: true,
+        };
+
+        for (name, kind) in [
+            ("empty", &symbols.empty),
+            ("roll", &symbols.roll),
+            ("to remove", &symbols.to_remove),
+        ] {
+            if kind.is_empty() {
+                bail!("There must be at least one {} symbol", name);
+            }
+        }
+
+        let all = [&symbols.empty, &symbols.roll, &symbols.to_remove]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&char>>();
+        for (i, symbol) in all.iter().enumerate() {
+            if all[..i].contains(symbol) {
+                bail!("Symbol '{}' is used more than once", symbol);
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    fn cell(&self, symbol: char) -> Option<Cell> {
+        match symbol {
+            symbol if self.empty.contains(&symbol) => Some(Cell::Empty),
+            symbol if self.roll.contains(&symbol) => Some(Cell::Roll),
+            symbol if self.to_remove.contains(&symbol) => Some(Cell::ToRemove),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self, cell: Cell) -> char {
+        match cell {
+            Cell::Empty => self.empty[0],
+            Cell::Roll => self.roll[0],
+            Cell::ToRemove => self.to_remove[0],
+        }
+    }
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Symbols::new(".", "@", "x").expect("default symbols are valid")
+    }
+}
+
+/// Reads a warehouse, skipping blank lines. Lines and columns in errors are
+/// 1-index and point into `rolls` as given.
+pub fn parse_rolls(rolls: &str, symbols: &Symbols) -> anyhow::Result<Grid<Cell>> {
+    let mut rows: Vec<Vec<Cell>> = Vec::new();
+    parse_rows(rolls, symbols, |row| rows.push(row))?;
+
+    Grid::from_rows(rows)
+}
+
+/// Same as `parse_rolls`, straight into one bit per cell
+pub fn parse_rolls_bits(rolls: &str, symbols: &Symbols) -> anyhow::Result<BitGrid> {
+    let mut grid = BitGrid::default();
+
+    parse_rows(rolls, symbols, |row| {
+        // Every row has the same length as the first one
+        grid.width = row.len();
+        grid.words_per_row = row.len().div_ceil(64);
+
+        let start = grid.bits.len();
+        grid.bits.resize(start + grid.words_per_row, 0);
+        for (j, cell) in row.iter().enumerate() {
+            if *cell != Cell::Empty {
+                grid.bits[start + j / 64] |= 1 << (j % 64);
+            }
+        }
+        grid.height += 1;
+    })?;
+
+    Ok(grid)
+}
+
+/// Same as `parse_rolls`, but only the cells that aren't empty are kept around
+pub fn parse_rolls_sparse(rolls: &str, symbols: &Symbols) -> anyhow::Result<SparseGrid> {
+    let mut grid = SparseGrid::default();
+    let mut height = 0;
+    let mut width = 0;
+
+    parse_rows(rolls, symbols, |row| {
+        for (j, cell) in row.iter().enumerate() {
+            if *cell != Cell::Empty {
+                grid.cells.insert((height, j as i64), *cell);
+            }
+        }
+        height += 1;
+        width = row.len() as i64;
+    })?;
+
+    grid.bounds = Some((height, width));
+    Ok(grid)
+}
+
+fn parse_rows(
+    rolls: &str,
+    symbols: &Symbols,
+    mut on_row: impl FnMut(Vec<Cell>),
+) -> anyhow::Result<()> {
+    // Length and line of the first row, that every other row must match
+    let mut first: Option<(usize, usize)> = None;
+
+    for (number, line) in rolls.lines().enumerate() {
+        let number = number + 1;
+
+        // Characters trimmed off the start, so columns still match the input
+        let (offset, line) = if symbols.trim {
+            let trimmed = line.trim_start();
+            let offset = line[..line.len() - trimmed.len()].chars().count();
+            (offset, trimmed.trim_end())
+        } else {
+            (0, line)
+        };
+
+        if line.is_empty() {
+            continue;
         }
 
-        if is_removable(grid, row, col) {
-            moved_rolls += 1;
-            if let Some(on_remove) = on_remove.as_mut() {
-                on_remove(grid, row, col)
+        let mut row = Vec::with_capacity(line.len());
+        for (column, symbol) in line.chars().enumerate() {
+            match symbols.cell(symbol) {
+                Some(cell) => row.push(cell),
+                None => bail!(
+                    "Line {}, column {}: unknown symbol '{}'",
+                    number,
+                    offset + column + 1,
+                    symbol
+                ),
             }
         }
-    });
 
-    moved_rolls
+        match first {
+            None => first = Some((row.len(), number)),
+            Some((length, first_line)) if length != row.len() => bail!(
+                "Line {} has {} cells, but line {} has {}",
+                number,
+                row.len(),
+                first_line,
+                length
+            ),
+            Some(_) => (),
+        }
+
+        on_row(row);
+    }
+
+    Ok(())
 }
 
-fn rolls_to_grid(rolls: &str) -> Vec<Vec<Cell>> {
-    rolls
-        .trim()
-        .lines()
-        .into_iter()
-        .filter(|line| !line.is_empty())
-        .map(|line| {
-            line.trim()
-                .split("")
-                .filter(|char| !char.is_empty())
-                .map(|char| match char {
-                    "." => Cell::Empty,
-                    "@" => Cell::Roll,
-                    other => panic!("Rolls can only be made of '.' or '@', got '{}'", other),
-                })
-                .collect::<Vec<Cell>>()
-        })
-        .collect::<Vec<Vec<Cell>>>()
+/// Writes a warehouse back out, one line per row
+pub fn write_rolls<W: Warehouse>(warehouse: &W, symbols: &Symbols) -> String {
+    warehouse.render(|position| symbols.symbol(warehouse.cell(position)))
 }
 
-pub fn read_rolls(path: &Path) -> Vec<Vec<Cell>> {
-    let mut file = File::open(path).expect("Couldn't open file");
+pub fn read_rolls(path: &Path, symbols: &Symbols) -> anyhow::Result<Grid<Cell>> {
+    let mut file = File::open(path).context("Couldn't open file")?;
     let mut rolls = String::new();
 
     file.read_to_string(&mut rolls)
-        .expect("Couldn't read contents");
+        .context("Couldn't read contents")?;
 
-    rolls_to_grid(&rolls)
+    parse_rolls(&rolls, symbols)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::d04_printing::{
-        get_accessable_rolls, get_accessable_rolls_with_removal, rolls_to_grid,
+    use crate::{
+        d04_printing::{
+            BitGrid, Boundary, Cell, Comparison, Neighborhood, Round, Rule, SparseGrid, Symbols,
+            Warehouse, get_accessable_rolls, get_accessable_rolls_bits,
+            get_accessable_rolls_with_removal, get_accessable_rolls_with_removal_bits,
+            get_neighbor_counts, get_removal_rounds, get_roll_statistics, parse_rolls,
+            parse_rolls_bits, parse_rolls_sparse, render_heatmap, write_rolls,
+        },
+        grid::Grid,
+        xorshift::XorShift,
     };
 
+    fn rolls_to_grid(rolls: &str) -> Grid<Cell> {
+        parse_rolls(rolls, &Symbols::default()).expect("Rolls in tests must be valid")
+    }
+
+    fn bits_from_grid(grid: &Grid<Cell>) -> BitGrid {
+        parse_rolls_bits(&grid.to_string(), &Symbols::default()).expect("Grids print as rolls")
+    }
+
+    fn sparse_from_grid(grid: &Grid<Cell>) -> SparseGrid {
+        parse_rolls_sparse(&grid.to_string(), &Symbols::default()).expect("Grids print as rolls")
+    }
+
+    fn unbounded(rolls: impl IntoIterator<Item = (i64, i64)>) -> SparseGrid {
+        SparseGrid {
+            cells: rolls
+                .into_iter()
+                .map(|position| (position, Cell::Roll))
+                .collect(),
+            bounds: None,
+        }
+    }
+
+    /// Warehouse with about two rolls in every three cells
+    fn random_rolls(rng: &mut XorShift, width: usize, height: usize) -> Grid<Cell> {
+        let mut grid = Grid::new(width, height, Cell::Empty);
+        for position in grid.positions() {
+            if !rng.next_u64().is_multiple_of(3) {
+                grid[position] = Cell::Roll;
+            }
+        }
+
+        grid
+    }
+
     #[test]
     fn test_get_accessable_rolls_valid() {
         let rolls = "
@@ -179,7 +1148,12 @@ mod test {
 	     ";
 
         let mut grid = rolls_to_grid(rolls);
-        let result = get_accessable_rolls::<fn(&mut Vec<Vec<_>>, usize, usize)>(&mut grid, None);
+        let result = get_accessable_rolls::<_, fn(&mut Grid<Cell>, (usize, usize))>(
+            &mut grid,
+            &Rule::default(),
+            None,
+        )
+        .unwrap();
 
         assert_eq!(13, result, "Expected {}, got {}", 13, result);
     }
@@ -200,8 +1174,506 @@ mod test {
 	     ";
 
         let mut grid = rolls_to_grid(rolls);
-        let result = get_accessable_rolls_with_removal(&mut grid);
+        let result = get_accessable_rolls_with_removal(&mut grid, &Rule::default()).unwrap();
 
         assert_eq!(43, result, "Expected {}, got {}", 43, result);
     }
+
+    #[test]
+    fn test_get_removal_rounds() {
+        let mut grid = rolls_to_grid(
+            "
+            @@@
+            @@@
+            ",
+        );
+
+        let mut states = Vec::new();
+        let rounds = get_removal_rounds(
+            &mut grid,
+            &Rule::default(),
+            Some(|grid: &Grid<Cell>, _: &Round| states.push(grid.to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            rounds,
+            vec![
+                Round {
+                    removed: vec![(0, 0), (0, 2), (1, 0), (1, 2)],
+                    remaining: 2,
+                },
+                Round {
+                    removed: vec![(0, 1), (1, 1)],
+                    remaining: 0,
+                },
+            ]
+        );
+        assert_eq!(states, vec!["x@x\nx@x\n", ".x.\n.x.\n"]);
+        assert_eq!(grid.to_string(), "...\n...\n");
+    }
+
+    #[test]
+    fn test_neighbor_counts_and_statistics() {
+        let grid = rolls_to_grid(
+            "
+            @@@@@
+            @@@@@
+            @@@@@
+            ....@
+            ",
+        );
+        let rule = Rule::default();
+
+        let counts = get_neighbor_counts(&grid, &rule).unwrap();
+        assert_eq!(
+            render_heatmap(&grid, &counts),
+            "35553\n58885\n35564\n....2\n"
+        );
+
+        let statistics = get_roll_statistics(&grid, &rule).unwrap();
+        assert_eq!(statistics.histogram, vec![0, 0, 1, 3, 1, 7, 1, 0, 3]);
+        assert_eq!(statistics.never_accessible, 0);
+        assert_eq!(statistics.depths[(0, 0)], Some(1));
+        assert_eq!(statistics.depths[(0, 2)], Some(5));
+        assert_eq!(statistics.depths[(1, 2)], Some(5));
+        assert_eq!(statistics.depths[(3, 4)], Some(1));
+        assert_eq!(statistics.depths[(3, 0)], None);
+
+        let rule = Rule {
+            boundary: Boundary::Roll,
+            ..Rule::default()
+        };
+        assert_eq!(
+            get_roll_statistics(&grid, &rule).unwrap().never_accessible,
+            16
+        );
+
+        // Same heatmap and rounds out of the sparse backend
+        let sparse = sparse_from_grid(&grid);
+        let statistics = get_roll_statistics(&sparse, &Rule::default()).unwrap();
+        assert_eq!(
+            render_heatmap(
+                &sparse,
+                &get_neighbor_counts(&sparse, &Rule::default()).unwrap()
+            ),
+            "35553\n58885\n35564\n....2\n"
+        );
+        assert_eq!(statistics.histogram, vec![0, 0, 1, 3, 1, 7, 1, 0, 3]);
+        assert_eq!(statistics.depths[(0, 2)], Some(5));
+        assert_eq!(statistics.depths[(3, 0)], None);
+    }
+
+    /// Rescans the whole grid every round, like part two was first solved
+    fn remove_by_rescanning(grid: &mut Grid<Cell>, rule: &Rule) -> u32 {
+        let mut moved_rolls = 0;
+
+        loop {
+            let new_moved_rolls = get_accessable_rolls(
+                grid,
+                rule,
+                Some(|grid: &mut Grid<Cell>, position: (usize, usize)| {
+                    grid[position] = Cell::ToRemove;
+                }),
+            )
+            .unwrap();
+
+            for position in grid.positions() {
+                if grid[position] == Cell::ToRemove {
+                    grid[position] = Cell::Empty;
+                }
+            }
+
+            if new_moved_rolls == 0 {
+                return moved_rolls;
+            }
+            moved_rolls += new_moved_rolls;
+        }
+    }
+
+    #[test]
+    fn test_removal_matches_rescanning() {
+        let mut rng = XorShift::new(0x853c49e6748fea9b);
+
+        let rules = [
+            Rule::default(),
+            Rule {
+                neighborhood: Neighborhood::VonNeumann(2),
+                threshold: 5,
+                comparison: Comparison::LessOrEqual,
+                boundary: Boundary::Wrap,
+            },
+            Rule {
+                boundary: Boundary::Roll,
+                ..Rule::default()
+            },
+            Rule {
+                neighborhood: Neighborhood::Custom(vec![(0, 1), (0, 2), (3, -1)]),
+                threshold: 1,
+                comparison: Comparison::Equal,
+                boundary: Boundary::Wrap,
+            },
+            Rule {
+                threshold: 5,
+                comparison: Comparison::Greater,
+                ..Rule::default()
+            },
+        ];
+
+        for _ in 0..40 {
+            let width = (rng.next_u64() % 12 + 1) as usize;
+            let height = (rng.next_u64() % 12 + 1) as usize;
+            let grid = random_rolls(&mut rng, width, height);
+
+            for rule in rules.iter() {
+                let mut expected_grid = grid.clone();
+                let expected = remove_by_rescanning(&mut expected_grid, rule);
+
+                let mut result_grid = grid.clone();
+                let result = get_accessable_rolls_with_removal(&mut result_grid, rule).unwrap();
+
+                assert_eq!(result, expected, "Wrong total with {:?} on\n{}", rule, grid);
+                assert_eq!(result_grid, expected_grid);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_grid_matches_dense_grid() {
+        let mut rng = XorShift::new(0xda3e39cb94b95bdb);
+
+        for _ in 0..30 {
+            // Wide enough to cross word boundaries, sometimes exactly on one
+            let width = match rng.next_u64() % 4 {
+                0 => 64,
+                1 => 128,
+                _ => (rng.next_u64() % 150 + 1) as usize,
+            };
+            let height = (rng.next_u64() % 20 + 1) as usize;
+            let mut grid = random_rolls(&mut rng, width, height);
+
+            let rule = Rule {
+                threshold: (rng.next_u64() % 10) as usize,
+                comparison: match rng.next_u64() % 5 {
+                    0 => Comparison::Less,
+                    1 => Comparison::LessOrEqual,
+                    2 => Comparison::Equal,
+                    3 => Comparison::GreaterOrEqual,
+                    _ => Comparison::Greater,
+                },
+                boundary: match rng.next_u64() % 2 {
+                    0 => Boundary::Empty,
+                    _ => Boundary::Roll,
+                },
+                ..Rule::default()
+            };
+
+            let bits = bits_from_grid(&grid);
+            let expected = get_accessable_rolls::<_, fn(&mut Grid<Cell>, (usize, usize))>(
+                &mut grid.clone(),
+                &rule,
+                None,
+            )
+            .unwrap();
+            assert_eq!(get_accessable_rolls_bits(&bits, &rule).unwrap(), expected);
+
+            let mut bits = bits;
+            let expected = get_accessable_rolls_with_removal(&mut grid, &rule).unwrap();
+            assert_eq!(
+                get_accessable_rolls_with_removal_bits(&mut bits, &rule).unwrap(),
+                expected,
+                "Wrong total with {:?} on\n{}",
+                rule,
+                grid
+            );
+            assert_eq!(bits, bits_from_grid(&grid));
+        }
+
+        let bits = bits_from_grid(&Grid::new(3, 3, Cell::Roll));
+        let rule = Rule {
+            boundary: Boundary::Wrap,
+            ..Rule::default()
+        };
+        assert!(bits.accessible(&rule).is_err());
+
+        // Any order of the 8 adjacent offsets is the moore neighborhood
+        let mut offsets = Neighborhood::Moore(1).offsets();
+        offsets.reverse();
+        let rule = Rule {
+            neighborhood: Neighborhood::Custom(offsets),
+            ..Rule::default()
+        };
+        let grid = rolls_to_grid("@@@\n@@@\n");
+        assert_eq!(
+            get_accessable_rolls_bits(&bits_from_grid(&grid), &rule).unwrap(),
+            4
+        );
+
+        let rule = Rule {
+            neighborhood: Neighborhood::Custom(vec![(0, 1); 8]),
+            ..Rule::default()
+        };
+        assert!(bits.accessible(&rule).is_err());
+    }
+
+    #[test]
+    fn test_parse_rolls_errors() {
+        let error = parse_rolls("..@\n.#@\n", &Symbols::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Line 2, column 2: unknown symbol '#'");
+
+        let error = parse_rolls("\n    ..@\n    @?.\n", &Symbols::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Line 3, column 6: unknown symbol '?'");
+
+        let error = parse_rolls("..@\n\n.@\n", &Symbols::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Line 3 has 2 cells, but line 1 has 3");
+
+        assert!(Symbols::new(".", ".@", "x").is_err());
+        assert!(Symbols::new(".", "", "x").is_err());
+    }
+
+    #[test]
+    fn test_parse_and_write_rolls_with_symbols() {
+        let mut symbols = Symbols::new(" -", "#O", "*").unwrap();
+        symbols.trim = false;
+
+        let grid = parse_rolls("  #-\nO*# \n", &symbols).unwrap();
+        assert_eq!(grid.to_string(), "..@.\n@x@.\n");
+        assert_eq!(write_rolls(&grid, &symbols), "  # \n#*# \n");
+
+        let rolls = "..@@.\n@x@.@\n";
+        let grid = parse_rolls(rolls, &Symbols::default()).unwrap();
+        assert_eq!(write_rolls(&grid, &Symbols::default()), rolls);
+    }
+
+    #[test]
+    fn test_marked_rolls_are_rolls_on_every_backend() {
+        let rule = Rule::default();
+
+        for (rolls, expected) in [("@@@\n@@x\n", (4, 6)), ("x@.\n@@@\n.x@\n", (2, 7))] {
+            let marked = rolls_to_grid(rolls);
+            let plain = rolls_to_grid(&rolls.replace('x', "@"));
+
+            let part_one = |mut grid: Grid<Cell>| {
+                get_accessable_rolls::<_, fn(&mut Grid<Cell>, (usize, usize))>(
+                    &mut grid, &rule, None,
+                )
+                .unwrap()
+            };
+            let part_two =
+                |mut grid: Grid<Cell>| get_accessable_rolls_with_removal(&mut grid, &rule).unwrap();
+            assert_eq!((part_one(plain.clone()), part_two(plain)), expected);
+            assert_eq!(
+                (part_one(marked.clone()), part_two(marked.clone())),
+                expected
+            );
+
+            let mut sparse = sparse_from_grid(&marked);
+            assert_eq!(
+                get_accessable_rolls::<_, fn(&mut SparseGrid, (i64, i64))>(
+                    &mut sparse.clone(),
+                    &rule,
+                    None
+                )
+                .unwrap(),
+                expected.0
+            );
+            assert_eq!(
+                get_accessable_rolls_with_removal(&mut sparse, &rule).unwrap(),
+                expected.1
+            );
+
+            let mut bits = bits_from_grid(&marked);
+            assert_eq!(get_accessable_rolls_bits(&bits, &rule).unwrap(), expected.0);
+            assert_eq!(
+                get_accessable_rolls_with_removal_bits(&mut bits, &rule).unwrap(),
+                expected.1
+            );
+        }
+    }
+
+    #[test]
+    fn test_sparse_grid_matches_dense_grid() {
+        let mut rng = XorShift::new(0x6a09e667f3bcc909);
+
+        let rules = [
+            Rule::default(),
+            Rule {
+                neighborhood: Neighborhood::VonNeumann(2),
+                threshold: 5,
+                comparison: Comparison::LessOrEqual,
+                boundary: Boundary::Wrap,
+            },
+            Rule {
+                boundary: Boundary::Roll,
+                ..Rule::default()
+            },
+            Rule {
+                neighborhood: Neighborhood::Custom(vec![(0, 1), (0, 2), (3, -1)]),
+                threshold: 1,
+                comparison: Comparison::Equal,
+                boundary: Boundary::Wrap,
+            },
+        ];
+
+        for _ in 0..30 {
+            let width = (rng.next_u64() % 12 + 1) as usize;
+            let height = (rng.next_u64() % 12 + 1) as usize;
+            let grid = random_rolls(&mut rng, width, height);
+
+            for rule in rules.iter() {
+                let sparse = sparse_from_grid(&grid);
+                let expected = get_accessable_rolls::<_, fn(&mut Grid<Cell>, (usize, usize))>(
+                    &mut grid.clone(),
+                    rule,
+                    None,
+                )
+                .unwrap();
+                let result = get_accessable_rolls::<_, fn(&mut SparseGrid, (i64, i64))>(
+                    &mut sparse.clone(),
+                    rule,
+                    None,
+                )
+                .unwrap();
+                assert_eq!(result, expected);
+
+                let mut sparse = sparse;
+                let mut dense = grid.clone();
+                let expected =
+                    get_removal_rounds::<_, fn(&Grid<Cell>, &Round)>(&mut dense, rule, None)
+                        .unwrap();
+                let result = get_removal_rounds::<_, fn(&SparseGrid, &Round<(i64, i64)>)>(
+                    &mut sparse,
+                    rule,
+                    None,
+                )
+                .unwrap();
+
+                // Same rolls in the same rounds, only the positions are stored differently
+                assert_eq!(
+                    result.len(),
+                    expected.len(),
+                    "Wrong rounds with {:?} on\n{}",
+                    rule,
+                    grid
+                );
+                for (result, expected) in result.iter().zip(expected.iter()) {
+                    let removed = expected
+                        .removed
+                        .iter()
+                        .map(|(i, j)| (*i as i64, *j as i64))
+                        .collect::<Vec<(i64, i64)>>();
+                    assert_eq!(result.removed, removed);
+                    assert_eq!(result.remaining, expected.remaining);
+                }
+                assert_eq!(sparse, sparse_from_grid(&dense));
+                assert_eq!(sparse.to_string(), dense.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_unbounded_sparse_grid() {
+        // Two blocks of rolls a long way apart
+        let mut rolls = Vec::new();
+        for (i, j) in [(-1_000_000_000, -3), (0, 0), (2_000_000_000, 2_000_000_000)] {
+            for di in 0..2 {
+                for dj in 0..3 {
+                    rolls.push((i + di, j + dj));
+                }
+            }
+        }
+        let mut grid = unbounded(rolls);
+
+        let rule = Rule::default();
+        let offsets = rule.neighborhood.offsets();
+        assert_eq!(rule.count_neighbors(&grid, &offsets, (0, 1)), 5);
+        assert_eq!(
+            get_accessable_rolls::<_, fn(&mut SparseGrid, (i64, i64))>(
+                &mut grid.clone(),
+                &rule,
+                None
+            )
+            .unwrap(),
+            12
+        );
+        assert_eq!(
+            get_accessable_rolls_with_removal(&mut grid, &rule).unwrap(),
+            18
+        );
+
+        let rule = Rule {
+            boundary: Boundary::Wrap,
+            ..Rule::default()
+        };
+        assert!(get_neighbor_counts(&grid, &rule).is_err());
+
+        let mut parsed = parse_rolls_sparse("..@\n@@.\n", &Symbols::default()).unwrap();
+        assert_eq!(parsed.roll_positions(), vec![(0, 2), (1, 0), (1, 1)]);
+        assert_eq!(parsed.cell((0, 0)), Cell::Empty);
+
+        // Without bounds only the cells around the rolls are drawn
+        parsed.set_cell((-1, 3), Cell::ToRemove);
+        assert_eq!(
+            parsed.clone().into_unbounded().to_string(),
+            "...x\n..@.\n@@..\n"
+        );
+        assert_eq!(parsed.to_string(), "..@\n@@.\n");
+    }
+
+    #[test]
+    fn test_rule_neighborhoods_and_boundaries() {
+        let grid = rolls_to_grid(
+            "
+            @.@
+            .@.
+            @@@
+            ",
+        );
+
+        let count =
+            |rule: &Rule, i, j| rule.count_neighbors(&grid, &rule.neighborhood.offsets(), (i, j));
+
+        let rule = Rule::default();
+        assert_eq!(count(&rule, 1, 1), 5);
+        assert_eq!(count(&rule, 0, 0), 1);
+
+        let rule = Rule {
+            neighborhood: Neighborhood::VonNeumann(1),
+            ..Rule::default()
+        };
+        assert_eq!(count(&rule, 1, 1), 1);
+
+        let rule = Rule {
+            neighborhood: Neighborhood::VonNeumann(2),
+            ..Rule::default()
+        };
+        assert_eq!(Neighborhood::VonNeumann(2).offsets().len(), 12);
+        assert_eq!(count(&rule, 0, 0), 3);
+
+        let rule = Rule {
+            boundary: Boundary::Roll,
+            ..Rule::default()
+        };
+        assert_eq!(count(&rule, 0, 0), 6);
+
+        // On a torus every cell of a 3x3 grid is a neighbor of every other
+        let rule = Rule {
+            boundary: Boundary::Wrap,
+            ..Rule::default()
+        };
+        assert_eq!(count(&rule, 0, 0), 5);
+        assert_eq!(count(&rule, 0, 1), 6);
+
+        let rule = Rule {
+            neighborhood: "custom:1,0;2,0".parse().unwrap(),
+            threshold: 1,
+            comparison: ">".parse().unwrap(),
+            ..Rule::default()
+        };
+        assert_eq!(rule.comparison, Comparison::Greater);
+        assert_eq!(count(&rule, 0, 0), 1);
+        let offsets = rule.neighborhood.offsets();
+        assert!(!rule.is_accessible(&grid, &offsets, (0, 0)));
+        assert!(rule.is_accessible(&grid, &offsets, (0, 1)));
+    }
 }
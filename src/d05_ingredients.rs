@@ -1,231 +1,495 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Read},
-    ops::Range,
-    path::Path,
-};
-
-#[derive(Debug, PartialEq)]
-enum SearchResult {
-    Inside(usize),
-    Outside(usize),
-}
-
-impl SearchResult {
-    fn unwrap(&self) -> usize {
-        match self {
-            SearchResult::Inside(i) | SearchResult::Outside(i) => *i,
-        }
-    }
-}
+use std::{fs::File, io::Read, ops::RangeInclusive, path::Path};
+
+use anyhow::{Context, bail};
 
-enum RelativeToRange {
-    Left,
-    In,
-    Right,
+use crate::interval_set::{IntervalSet, OverlapIndex, SearchResult};
+
+pub fn find_valid_ids(ids: Vec<u128>, ranges: &IntervalSet<u128>) -> u128 {
+    ids.iter()
+        .map(|id| match ranges.find(*id) {
+            SearchResult::Inside(_) => 1,
+            SearchResult::Outside(_) => 0,
+        })
+        .sum()
 }
 
-fn position_relative_to_range(id: u128, range: &Range<u128>) -> RelativeToRange {
-    match range {
-        _ if id >= range.start && id <= range.end => RelativeToRange::In,
-        _ if id < range.start => RelativeToRange::Left,
-        _ => RelativeToRange::Right,
-    }
+/// Same count as `find_valid_ids`, from a single sorted pass over the IDs
+pub fn find_valid_ids_merged(ids: &[u128], ranges: &IntervalSet<u128>) -> u128 {
+    ranges
+        .contains_many(ids)
+        .into_iter()
+        .filter(|fresh| *fresh)
+        .count() as u128
 }
 
-pub fn find_id(id: u128, ids: &[Range<u128>]) -> SearchResult {
-    find_id_from_range(
-        id,
-        ids,
-        Range {
-            start: 0,
-            end: ids.len(),
-        },
-    )
+#[derive(Debug, Clone, PartialEq)]
+pub enum Freshness {
+    /// With the merged range it's in
+    Fresh(RangeInclusive<u128>),
+    /// With the closest ranges on either side, if there are any
+    Spoiled {
+        below: Option<RangeInclusive<u128>>,
+        above: Option<RangeInclusive<u128>>,
+    },
 }
 
-fn find_id_from_range(id: u128, ids: &[Range<u128>], sliced: Range<usize>) -> SearchResult {
-    assert!(sliced.start <= ids.len());
-    assert!(sliced.end <= ids.len());
+/// Every ID, in the order given, with whether it's fresh and why
+pub fn audit_ids(ids: &[u128], ranges: &IntervalSet<u128>) -> Vec<(u128, Freshness)> {
+    ids.iter()
+        .map(|id| {
+            let freshness = match ranges.find(*id) {
+                SearchResult::Inside(i) => Freshness::Fresh(
+                    ranges
+                        .get(i)
+                        .cloned()
+                        .expect("find gives back a valid index"),
+                ),
+                SearchResult::Outside(i) => Freshness::Spoiled {
+                    below: i.checked_sub(1).and_then(|i| ranges.get(i)).cloned(),
+                    above: ranges.get(i).cloned(),
+                },
+            };
 
-    if sliced.start == sliced.end {
-        let checked_id = match ids.get(sliced.start) {
-            Some(id) => id,
-            None => return SearchResult::Outside(sliced.start),
-        };
+            (*id, freshness)
+        })
+        .collect()
+}
+
+/// CSV with a header, one ID per line: `id,status,range,below,above`
+pub fn render_audit(audit: &[(u128, Freshness)]) -> String {
+    let render = |range: &Option<RangeInclusive<u128>>| match range {
+        Some(range) => format!("{}-{}", range.start(), range.end()),
+        None => String::new(),
+    };
 
-        return match position_relative_to_range(id, checked_id) {
-            RelativeToRange::In => SearchResult::Inside(sliced.start),
-            RelativeToRange::Left => SearchResult::Outside(sliced.start),
-            RelativeToRange::Right => SearchResult::Outside(sliced.start + 1),
+    let mut csv = String::from("id,status,range,below,above\n");
+    for (id, freshness) in audit {
+        let line = match freshness {
+            Freshness::Fresh(range) => {
+                format!("{},fresh,{},,", id, render(&Some(range.clone())))
+            }
+            Freshness::Spoiled { below, above } => {
+                format!("{},spoiled,,{},{}", id, render(below), render(above))
+            }
         };
+        csv.push_str(&line);
+        csv.push('\n');
     }
 
-    let middle = ((sliced.end - sliced.start) / 2usize) + sliced.start;
-    let middle_id = &ids[middle];
-
-    match position_relative_to_range(id, middle_id) {
-        RelativeToRange::In => SearchResult::Inside(middle),
-        RelativeToRange::Left => find_id_from_range(
-            id,
-            ids,
-            Range {
-                start: sliced.start,
-                end: middle,
-            },
-        ),
-        RelativeToRange::Right => find_id_from_range(
-            id,
-            ids,
-            Range {
-                start: middle + 1,
-                end: sliced.end,
-            },
-        ),
+    csv
+}
+
+pub fn get_total_fresh(ranges: &IntervalSet<u128>) -> u128 {
+    ranges.len()
+}
+
+type LabelledRanges = Vec<(RangeInclusive<u128>, usize)>;
+
+/// `start-end`, with errors pointing at the line it's on when there is one
+fn parse_range(line: &str, number: Option<usize>) -> anyhow::Result<RangeInclusive<u128>> {
+    let at = number.map_or(String::new(), |number| format!("Line {}: ", number));
+
+    let Some((start, end)) = line.split_once('-') else {
+        bail!("{}expected a range like 3-5, found '{}'", at, line);
+    };
+
+    let start: u128 = start
+        .trim()
+        .parse()
+        .with_context(|| format!("{}range start '{}' is not an ID", at, start))?;
+    let end: u128 = end
+        .trim()
+        .parse()
+        .with_context(|| format!("{}range end '{}' is not an ID", at, end))?;
+
+    if start > end {
+        bail!("{}range {}-{} starts after it ends", at, start, end);
     }
+
+    Ok(start..=end)
 }
 
-fn insert_range(ids: &mut Vec<Range<u128>>, range: Range<u128>) {
-    assert!(range.start <= range.end);
+fn parse_id(number: usize, line: &str) -> anyhow::Result<u128> {
+    line.parse().with_context(|| {
+        format!(
+            "Line {}: expected an ID in the IDs section, found '{}'",
+            number, line
+        )
+    })
+}
 
-    let start = find_id(range.start, ids);
-    let end = find_id(range.end, ids);
+/// Lines with their 1-index number, trimmed, without comments, for a file
+/// with a single section. Blank lines can only come at the end.
+fn content_lines<'a>(text: &'a str, section: &str) -> anyhow::Result<Vec<(usize, &'a str)>> {
+    let mut lines = Vec::new();
+    let mut blank: Option<usize> = None;
 
-    match (&start, &end) {
-        (SearchResult::Inside(start), SearchResult::Inside(end)) => {
-            ids[*start].end = ids[*end].end;
-        }
-        (SearchResult::Inside(start), SearchResult::Outside(_)) => {
-            ids[*start].end = range.end;
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            blank.get_or_insert(number);
+            continue;
         }
-        (SearchResult::Outside(_), SearchResult::Inside(end)) => {
-            ids[*end].start = range.start;
+
+        if line.starts_with('#') {
+            continue;
         }
-        (SearchResult::Outside(start), SearchResult::Outside(_)) => {
-            ids.insert(*start, range);
+
+        match blank {
+            Some(blank) if lines.is_empty() => bail!(
+                "Line {}: blank line {} comes before the {}",
+                number,
+                blank,
+                section
+            ),
+            Some(blank) => bail!(
+                "Line {}: the {} ended with the blank line {}, but more follows",
+                number,
+                section,
+                blank
+            ),
+            None => lines.push((number, line)),
         }
     }
 
-    if &start.unwrap() < &end.unwrap() {
-        match (&start, &end) {
-            (SearchResult::Inside(start), SearchResult::Inside(end))
-            | (SearchResult::Outside(start), SearchResult::Outside(end)) => {
-                ids.drain((*start + 1)..(*end + 1));
-            }
-            (_, _) => {
-                ids.drain((start.unwrap() + 1)..end.unwrap());
+    Ok(lines)
+}
+
+/// Reads a database with the ranges first and the IDs after, split by a single
+/// blank line. Lines starting with `#` are comments, and blank lines are only
+/// fine at the end. Ranges come back labelled with the line they were on.
+pub fn parse_ids(database: &str) -> anyhow::Result<(LabelledRanges, Vec<u128>)> {
+    let mut ranges = Vec::new();
+    let mut ids = Vec::new();
+    // Whether the blank line between the sections was found
+    let mut in_ids = false;
+    // What's wrong with the first blank line out of place, only an error if
+    // something other than blank lines and comments follows it
+    let mut stray_blank: Option<String> = None;
+
+    for (number, line) in database.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if line.is_empty() {
+            if stray_blank.is_none() {
+                match (in_ids, ranges.is_empty(), ids.is_empty()) {
+                    (false, true, _) => {
+                        stray_blank = Some(format!("blank line {} comes before the ranges", number))
+                    }
+                    (false, false, _) => in_ids = true,
+                    (true, _, true) => {
+                        stray_blank = Some(format!(
+                            "blank line {} is a second one between the ranges and the IDs",
+                            number
+                        ))
+                    }
+                    (true, _, false) => {
+                        stray_blank = Some(format!(
+                            "the IDs section ended with the blank line {}, but more follows",
+                            number
+                        ))
+                    }
+                }
             }
+            continue;
+        }
+
+        if let Some(stray_blank) = stray_blank {
+            bail!("Line {}: {}", number, stray_blank);
+        }
+
+        if in_ids {
+            ids.push(parse_id(number, line)?);
+        } else {
+            ranges.push((parse_range(line, Some(number))?, number));
         }
     }
+
+    Ok((ranges, ids))
 }
 
-pub fn find_valid_ids(ids: Vec<u128>, ranges: &Vec<Range<u128>>) -> u128 {
-    ids.iter()
-        .map(|id| match find_id(*id, &ranges) {
-            SearchResult::Inside(_) => 1,
-            SearchResult::Outside(_) => 0,
-        })
-        .reduce(|acc, result| acc + result)
-        .unwrap()
+/// A file with only ranges, and nothing else, in it
+pub fn parse_ranges_only(ranges: &str) -> anyhow::Result<LabelledRanges> {
+    content_lines(ranges, "ranges")?
+        .into_iter()
+        .map(|(number, line)| Ok((parse_range(line, Some(number))?, number)))
+        .collect()
 }
 
-pub fn get_total_fresh(ranges: &Vec<Range<u128>>) -> u128 {
+/// A file with only IDs, and nothing else, in it
+pub fn parse_ids_only(ids: &str) -> anyhow::Result<Vec<u128>> {
+    content_lines(ids, "IDs")?
+        .into_iter()
+        .map(|(number, line)| parse_id(number, line))
+        .collect()
+}
+
+fn read_file(path: &Path) -> anyhow::Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Couldn't open file {}", path.display()))?;
+    let mut contents = String::new();
+
+    file.read_to_string(&mut contents)
+        .with_context(|| format!("Couldn't read contents of {}", path.display()))?;
+
+    Ok(contents)
+}
+
+pub fn read_ids(path: &Path) -> anyhow::Result<(OverlapIndex<u128>, Vec<u128>)> {
+    let (ranges, ids) = parse_ids(&read_file(path)?)
+        .with_context(|| format!("Couldn't parse {}", path.display()))?;
+
+    Ok((OverlapIndex::new(ranges), ids))
+}
+
+/// Same as `read_ids`, with the ranges and the IDs in their own files
+pub fn read_ids_split(
+    ranges: &Path,
+    ids: Option<&Path>,
+) -> anyhow::Result<(OverlapIndex<u128>, Vec<u128>)> {
+    let parsed_ranges = parse_ranges_only(&read_file(ranges)?)
+        .with_context(|| format!("Couldn't parse {}", ranges.display()))?;
+
+    let parsed_ids = match ids {
+        Some(ids) => parse_ids_only(&read_file(ids)?)
+            .with_context(|| format!("Couldn't parse {}", ids.display()))?,
+        None => Vec::new(),
+    };
+
+    Ok((OverlapIndex::new(parsed_ranges), parsed_ids))
+}
+
+fn render_ranges<'a>(ranges: impl Iterator<Item = &'a RangeInclusive<u128>>) -> String {
     ranges
-        .iter()
-        .map(|range| range.end - range.start + 1)
-        .reduce(|acc, result| acc + result)
-        .unwrap()
+        .map(|range| format!("{}-{}", range.start(), range.end()))
+        .collect::<Vec<String>>()
+        .join("\n")
 }
 
-pub fn read_ids(path: &Path) -> (Vec<Range<u128>>, Vec<u128>) {
-    let file = File::open(&path).expect("Error while opening file");
-    let reader = BufReader::new(file);
+/// Runs one line of the interactive mode against the ranges, giving back what
+/// to print: `check <id>`, `add <a>-<b>`, `remove <a>-<b>`, `count`, `gaps` or
+/// `dump`
+pub fn run_command(ranges: &mut IntervalSet<u128>, line: &str) -> anyhow::Result<String> {
+    let (command, argument) = match line.trim().split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line.trim(), ""),
+    };
 
-    let mut ranges: Vec<Range<u128>> = Vec::new();
-    let mut ids: Vec<u128> = Vec::new();
+    let count = |ranges: &IntervalSet<u128>| {
+        format!(
+            "{} fresh IDs in {} ranges",
+            ranges.len(),
+            ranges.iter().count()
+        )
+    };
 
-    let mut reading_ranges = true;
-    for line in reader.lines() {
-        let line = line.expect("Error reading line");
-        let line = line.trim();
-        if line.is_empty() {
-            reading_ranges = false;
-            continue;
+    match (command, argument) {
+        ("check", id) => {
+            let id: u128 = id
+                .parse()
+                .with_context(|| format!("Expected an ID to check, found '{}'", id))?;
+
+            match ranges.find(id) {
+                SearchResult::Inside(i) => {
+                    let range = ranges.get(i).expect("find gives back a valid index");
+                    Ok(format!(
+                        "{} is fresh, in {}-{}",
+                        id,
+                        range.start(),
+                        range.end()
+                    ))
+                }
+                SearchResult::Outside(_) => Ok(format!("{} is spoiled", id)),
+            }
+        }
+        ("add", range) => {
+            ranges.insert(parse_range(range, None)?);
+            Ok(count(ranges))
         }
+        ("remove", range) => {
+            ranges.remove(parse_range(range, None)?);
+            Ok(count(ranges))
+        }
+        ("count", "") => Ok(count(ranges)),
+        ("gaps", "") => {
+            let gaps = match ranges.bounds() {
+                Some(bounds) => ranges.complement(0..=*bounds.end()),
+                None => IntervalSet::new(),
+            };
 
-        if reading_ranges {
-            let chunks = line.split("-").collect::<Vec<&str>>();
-            insert_range(
-                &mut ranges,
-                Range {
-                    start: chunks[0].parse().unwrap(),
-                    end: chunks[1].parse().unwrap(),
-                },
-            );
-        } else {
-            ids.push(line.parse().unwrap())
+            if gaps.is_empty() {
+                Ok(String::from("No gaps"))
+            } else {
+                Ok(render_ranges(gaps.iter()))
+            }
         }
+        ("dump", "") if ranges.is_empty() => Ok(String::from("No ranges")),
+        ("dump", "") => Ok(render_ranges(ranges.iter())),
+        ("count" | "gaps" | "dump", argument) => {
+            bail!("'{}' takes no arguments, found '{}'", command, argument)
+        }
+        (command, _) => bail!(
+            "Unknown command '{}', try check, add, remove, count, gaps or dump",
+            command
+        ),
     }
-
-    (ranges, ids)
 }
 
 #[cfg(test)]
 mod test {
-    use std::ops::Range;
+    use crate::{
+        d05_ingredients::{
+            Freshness, audit_ids, parse_ids, parse_ids_only, parse_ranges_only, render_audit,
+            run_command,
+        },
+        interval_set::IntervalSet,
+    };
 
-    use crate::d05_ingredients::{SearchResult, find_id, insert_range};
+    #[test]
+    fn test_audit_ids() {
+        let ranges = IntervalSet::from_iter([3..=5, 10..=14]);
+        let audit = audit_ids(&[12, 1, 7, 20, 5], &ranges);
+
+        assert_eq!(
+            audit,
+            vec![
+                (12, Freshness::Fresh(10..=14)),
+                (
+                    1,
+                    Freshness::Spoiled {
+                        below: None,
+                        above: Some(3..=5)
+                    }
+                ),
+                (
+                    7,
+                    Freshness::Spoiled {
+                        below: Some(3..=5),
+                        above: Some(10..=14)
+                    }
+                ),
+                (
+                    20,
+                    Freshness::Spoiled {
+                        below: Some(10..=14),
+                        above: None
+                    }
+                ),
+                (5, Freshness::Fresh(3..=5)),
+            ]
+        );
+
+        assert_eq!(
+            render_audit(&audit),
+            "id,status,range,below,above\n\
+             12,fresh,10-14,,\n\
+             1,spoiled,,,3-5\n\
+             7,spoiled,,3-5,10-14\n\
+             20,spoiled,,10-14,\n\
+             5,fresh,3-5,,\n"
+        );
+    }
 
     #[test]
-    fn test_find_id() {
-        let ids = vec![
-            Range { start: 3, end: 5 },
-            Range { start: 7, end: 9 },
-            Range { start: 11, end: 15 },
-            Range { start: 17, end: 20 },
-        ];
-
-        assert_eq!(find_id(4, &ids), SearchResult::Inside(0));
-        assert_eq!(find_id(8, &ids), SearchResult::Inside(1));
-        assert_eq!(find_id(12, &ids), SearchResult::Inside(2));
-        assert_eq!(find_id(17, &ids), SearchResult::Inside(3));
-        assert_eq!(find_id(21, &ids), SearchResult::Outside(4));
-        assert_eq!(find_id(0, &ids), SearchResult::Outside(0));
-        assert_eq!(find_id(10, &ids), SearchResult::Outside(2));
+    fn test_parse_ids() {
+        let database = "# Fresh ranges\n3-5\n 10 - 14 \n\n# IDs\n1\n5\n\n\n";
+        let (ranges, ids) = parse_ids(database).unwrap();
+
+        assert_eq!(ranges, vec![(3..=5, 2), (10..=14, 3)]);
+        assert_eq!(ids, vec![1, 5]);
+
+        let (ranges, ids) = parse_ids("3-5\n").unwrap();
+        assert_eq!(ranges, vec![(3..=5, 1)]);
+        assert!(ids.is_empty());
+
+        // Only blank lines at the end are fine
+        let (ranges, ids) = parse_ids("3-5\n\n\n").unwrap();
+        assert_eq!(ranges, vec![(3..=5, 1)]);
+        assert!(ids.is_empty());
+
+        assert_eq!(
+            parse_ranges_only("1-2\n# More\n4-4\n\n").unwrap(),
+            vec![(1..=2, 1), (4..=4, 3)]
+        );
+        assert_eq!(parse_ids_only("7\n8\n\n").unwrap(), vec![7, 8]);
     }
 
     #[test]
-    fn test_insert_range() {
-        let mut ids = vec![Range { start: 3, end: 5 }];
+    fn test_parse_ids_errors() {
+        let error = |database: &str| format!("{:#}", parse_ids(database).unwrap_err());
 
-        insert_range(&mut ids, Range { start: 4, end: 6 });
-        assert_eq!(ids, vec![Range { start: 3, end: 6 }]);
-        insert_range(&mut ids, Range { start: 1, end: 2 });
         assert_eq!(
-            ids,
-            vec![Range { start: 1, end: 2 }, Range { start: 3, end: 6 }]
+            error("3-5\n17\n"),
+            "Line 2: expected a range like 3-5, found '17'"
         );
-        insert_range(&mut ids, Range { start: 7, end: 8 });
+        assert!(error("3-x\n").starts_with("Line 1: range end 'x' is not an ID"));
+        assert!(error("-5\n").starts_with("Line 1: range start '' is not an ID"));
         assert_eq!(
-            ids,
-            vec![
-                Range { start: 1, end: 2 },
-                Range { start: 3, end: 6 },
-                Range { start: 7, end: 8 }
-            ]
+            error("1-2\n9-4\n"),
+            "Line 2: range 9-4 starts after it ends"
+        );
+        assert!(
+            error("1-2\n\n4\n5-6\n")
+                .starts_with("Line 4: expected an ID in the IDs section, found '5-6'")
+        );
+        assert_eq!(
+            error("1-2\n\n4\n\n5\n"),
+            "Line 5: the IDs section ended with the blank line 4, but more follows"
+        );
+        assert_eq!(
+            error("1-2\n\n\n4\n"),
+            "Line 4: blank line 3 is a second one between the ranges and the IDs"
+        );
+        assert_eq!(
+            error("1-2\n\n# IDs\n\n4\n"),
+            "Line 5: blank line 4 is a second one between the ranges and the IDs"
+        );
+        assert_eq!(
+            error("\n1-2\n\n4\n"),
+            "Line 2: blank line 1 comes before the ranges"
         );
-        insert_range(&mut ids, Range { start: 0, end: 10 });
-        assert_eq!(ids, vec![Range { start: 0, end: 10 },]);
-        insert_range(&mut ids, Range { start: 12, end: 14 });
-        insert_range(&mut ids, Range { start: 14, end: 18 });
+
+        assert!(parse_ids_only("1\n2-3\n").is_err());
         assert_eq!(
-            ids,
-            vec![Range { start: 0, end: 10 }, Range { start: 12, end: 18 }]
+            format!("{:#}", parse_ids_only("7\n\n8\n").unwrap_err()),
+            "Line 3: the IDs ended with the blank line 2, but more follows"
         );
-        insert_range(&mut ids, Range { start: 13, end: 19 });
         assert_eq!(
-            ids,
-            vec![Range { start: 0, end: 10 }, Range { start: 12, end: 19 }]
+            format!("{:#}", parse_ranges_only("\n1-2\n").unwrap_err()),
+            "Line 2: blank line 1 comes before the ranges"
+        );
+    }
+
+    #[test]
+    fn test_run_command() {
+        let mut ranges = IntervalSet::from_iter([3..=5, 10..=14]);
+        let mut run = |line: &str| run_command(&mut ranges, line);
+
+        assert_eq!(run("check 12").unwrap(), "12 is fresh, in 10-14");
+        assert_eq!(run("check 7").unwrap(), "7 is spoiled");
+        assert_eq!(run("count").unwrap(), "8 fresh IDs in 2 ranges");
+        assert_eq!(run("gaps").unwrap(), "0-2\n6-9");
+        assert_eq!(run("add 6-8").unwrap(), "11 fresh IDs in 2 ranges");
+        assert_eq!(run("  remove 4-4 ").unwrap(), "10 fresh IDs in 3 ranges");
+        assert_eq!(run("dump").unwrap(), "3-3\n5-8\n10-14");
+        assert_eq!(run("remove 0-20").unwrap(), "0 fresh IDs in 0 ranges");
+        assert_eq!(run("dump").unwrap(), "No ranges");
+        assert_eq!(run("gaps").unwrap(), "No gaps");
+
+        assert!(run("check").is_err());
+        assert!(run("add 5-3").is_err());
+        assert_eq!(
+            format!("{:#}", run("add 5").unwrap_err()),
+            "expected a range like 3-5, found '5'"
         );
+        assert!(run("count 3").is_err());
+        assert!(run("eat 3").is_err());
     }
 }
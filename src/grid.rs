@@ -0,0 +1,268 @@
+//! Flat, row-major grid shared by the days that work on maps.
+//!
+//! Positions are `(i, j)` = (row, column), like everywhere else.
+
+use std::{
+    fmt,
+    ops::{Index, IndexMut},
+};
+
+use anyhow::bail;
+
+/// Up, left, right and down
+const OFFSETS_4: [(isize, isize); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+/// Every cell around, corners included, in row-major order
+pub const OFFSETS_8: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+impl<T> Grid<T> {
+    pub fn new(width: usize, height: usize, fill: T) -> Self
+    where
+        T: Clone,
+    {
+        Grid {
+            width,
+            height,
+            cells: vec![fill; width * height],
+        }
+    }
+
+    /// Builds a grid out of its rows, which must all be the same length
+    pub fn from_rows(rows: Vec<Vec<T>>) -> anyhow::Result<Self> {
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        let mut cells = Vec::with_capacity(width * height);
+
+        for (i, row) in rows.into_iter().enumerate() {
+            if row.len() != width {
+                bail!(
+                    "Row {} has {} cells, expected {} like the first one",
+                    i,
+                    row.len(),
+                    width
+                );
+            }
+            cells.extend(row);
+        }
+
+        Ok(Grid {
+            width,
+            height,
+            cells,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Option<&T> {
+        if i >= self.height || j >= self.width {
+            return None;
+        }
+
+        self.cells.get(i * self.width + j)
+    }
+
+    pub fn get_mut(&mut self, i: usize, j: usize) -> Option<&mut T> {
+        if i >= self.height || j >= self.width {
+            return None;
+        }
+
+        self.cells.get_mut(i * self.width + j)
+    }
+
+    /// Position `(di, dj)` away from `(i, j)`, if it's still inside the grid
+    pub fn offset(&self, i: usize, j: usize, di: isize, dj: isize) -> Option<(usize, usize)> {
+        let i = i.checked_add_signed(di)?;
+        let j = j.checked_add_signed(dj)?;
+
+        match (i, j) {
+            (i, j) if i < self.height && j < self.width => Some((i, j)),
+            _ => None,
+        }
+    }
+
+    /// Position `(di, dj)` away from `(i, j)` on a grid whose edges wrap
+    /// around like a torus
+    pub fn offset_wrapping(&self, i: usize, j: usize, di: isize, dj: isize) -> (usize, usize) {
+        let i = (i as isize + di).rem_euclid(self.height as isize);
+        let j = (j as isize + dj).rem_euclid(self.width as isize);
+
+        (i as usize, j as usize)
+    }
+
+    /// Positions `offsets` away from `(i, j)` that are inside the grid
+    pub fn neighbors<'a>(
+        &'a self,
+        i: usize,
+        j: usize,
+        offsets: &'a [(isize, isize)],
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        offsets
+            .iter()
+            .filter_map(move |(di, dj)| self.offset(i, j, *di, *dj))
+    }
+
+    /// Positions `offsets` away from `(i, j)`, wrapping around the edges
+    pub fn neighbors_wrapping<'a>(
+        &'a self,
+        i: usize,
+        j: usize,
+        offsets: &'a [(isize, isize)],
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        offsets
+            .iter()
+            .map(move |(di, dj)| self.offset_wrapping(i, j, *di, *dj))
+    }
+
+    /// Every position, row by row
+    pub fn positions(&self) -> impl Iterator<Item = (usize, usize)> + use<T> {
+        let width = self.width;
+        (0..self.height).flat_map(move |i| (0..width).map(move |j| (i, j)))
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        // chunks panics on 0, but a grid without columns has no rows to give back
+        self.cells.chunks(self.width.max(1))
+    }
+}
+
+// Not every grid day needs these yet
+#[allow(dead_code)]
+impl<T> Grid<T> {
+    /// Up, left, right and down neighbors that are inside the grid
+    pub fn neighbors4(&self, i: usize, j: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.neighbors(i, j, &OFFSETS_4)
+    }
+
+    /// Neighbors including the corners that are inside the grid
+    pub fn neighbors8(&self, i: usize, j: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.neighbors(i, j, &OFFSETS_8)
+    }
+
+    pub fn column(&self, j: usize) -> Option<impl Iterator<Item = &T>> {
+        if j >= self.width {
+            return None;
+        }
+
+        Some(self.cells.iter().skip(j).step_by(self.width))
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> {
+        (0..self.width).map(|j| self.cells.iter().skip(j).step_by(self.width))
+    }
+}
+
+impl<T> Index<(usize, usize)> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, (i, j): (usize, usize)) -> &T {
+        self.get(i, j).expect("Position must be inside the grid")
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Grid<T> {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut T {
+        self.get_mut(i, j)
+            .expect("Position must be inside the grid")
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Grid<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows() {
+            for cell in row {
+                write!(f, "{}", cell)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grid::Grid;
+
+    #[test]
+    fn test_from_rows_rejects_ragged_rows() {
+        assert!(Grid::from_rows(vec![vec![1, 2], vec![3]]).is_err());
+
+        let grid = Grid::from_rows(vec![vec![1, 2, 3], vec![4, 5, 6]]).unwrap();
+        assert_eq!(grid.width(), 3);
+        assert_eq!(grid.height(), 2);
+        assert_eq!(grid.get(1, 2), Some(&6));
+        assert_eq!(grid.get(2, 0), None);
+        assert_eq!(grid.get(0, 3), None);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let grid = Grid::new(3, 3, 0);
+        let offsets = [(-1, -1), (0, 1), (1, 0), (1, 1)];
+
+        let corner = grid
+            .neighbors(0, 0, &offsets)
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(corner, vec![(0, 1), (1, 0), (1, 1)]);
+        assert_eq!(grid.neighbors(1, 1, &offsets).count(), 4);
+
+        let wrapped = grid
+            .neighbors_wrapping(0, 2, &offsets)
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(wrapped, vec![(2, 1), (0, 0), (1, 2), (1, 0)]);
+
+        let corner = grid.neighbors8(0, 0).collect::<Vec<(usize, usize)>>();
+        assert_eq!(corner, vec![(0, 1), (1, 0), (1, 1)]);
+
+        let center = grid.neighbors4(1, 1).collect::<Vec<(usize, usize)>>();
+        assert_eq!(center, vec![(0, 1), (1, 0), (1, 2), (2, 1)]);
+
+        assert_eq!(grid.neighbors8(1, 1).count(), 8);
+    }
+
+    #[test]
+    fn test_rows_columns_and_display() {
+        let grid = Grid::from_rows(vec![vec![1, 2, 3], vec![4, 5, 6]]).unwrap();
+
+        let columns = grid
+            .columns()
+            .map(|column| column.copied().collect::<Vec<i32>>())
+            .collect::<Vec<Vec<i32>>>();
+        assert_eq!(columns, vec![vec![1, 4], vec![2, 5], vec![3, 6]]);
+        assert_eq!(
+            grid.column(1)
+                .map(|column| column.copied().collect::<Vec<i32>>()),
+            Some(vec![2, 5])
+        );
+        assert!(grid.column(3).is_none());
+
+        let rows = grid.rows().collect::<Vec<&[i32]>>();
+        assert_eq!(rows, vec![&[1, 2, 3][..], &[4, 5, 6][..]]);
+        assert_eq!(grid.to_string(), "123\n456\n");
+
+        let empty: Grid<i32> = Grid::new(0, 2, 0);
+        assert_eq!(empty.rows().count(), 0);
+    }
+}
@@ -0,0 +1,295 @@
+//! Compact on-disk form of an `IntervalSet<u128>`, so a big set is merged
+//! once and then looked up straight from the file, without reading it all.
+//!
+//! Layout, integers in little endian:
+//! - `MAGIC`, then the number of ranges and of blocks as `u64`, and the
+//!   number of IDs the ranges cover as `u128`
+//! - A skip table with, for each block, the start of its first range as `u128`
+//!   and where the block begins, counted from the start of the blocks, as `u64`
+//! - The blocks, each with up to `BLOCK` ranges written as two varints: the
+//!   distance from the end of the previous range (or the block's first start),
+//!   and the length of the range minus one
+//!
+//! Lookups binary search the fixed width skip table, then only read and decode
+//! the one block that could hold the ID.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::RangeInclusive,
+};
+
+use anyhow::{Context, bail};
+
+use crate::interval_set::IntervalSet;
+
+const MAGIC: &[u8; 8] = b"D5INDEX2";
+const BLOCK: usize = 64;
+const HEADER: usize = MAGIC.len() + 8 + 8 + 16;
+const SKIP_ENTRY: usize = 16 + 8;
+
+/// LEB128, 7 bits at a time, lowest first
+fn write_varint(bytes: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Value and bytes it took
+fn read_varint(bytes: &[u8]) -> anyhow::Result<(u128, usize)> {
+    let mut value: u128 = 0;
+
+    for (k, byte) in bytes.iter().enumerate() {
+        let bits = (*byte & 0x7f) as u128;
+        let shift = 7 * k as u32;
+        if shift >= 128 || (shift > 0 && bits >> (128 - shift) != 0) {
+            bail!("Varint doesn't fit in 128 bits");
+        }
+
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, k + 1));
+        }
+    }
+
+    bail!("Index ends in the middle of a varint")
+}
+
+pub fn encode_index(set: &IntervalSet<u128>) -> Vec<u8> {
+    let ranges = set.iter().collect::<Vec<&RangeInclusive<u128>>>();
+    let blocks = ranges
+        .chunks(BLOCK)
+        .collect::<Vec<&[&RangeInclusive<u128>]>>();
+
+    let mut skip_table = Vec::with_capacity(blocks.len() * SKIP_ENTRY);
+    let mut encoded = Vec::new();
+    for block in blocks.iter() {
+        let first = *block[0].start();
+        skip_table.extend(first.to_le_bytes());
+        skip_table.extend((encoded.len() as u64).to_le_bytes());
+
+        let mut previous = first;
+        for range in block.iter() {
+            write_varint(&mut encoded, range.start() - previous);
+            write_varint(&mut encoded, range.end() - range.start());
+            previous = *range.end();
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER + skip_table.len() + encoded.len());
+    bytes.extend(MAGIC);
+    bytes.extend((ranges.len() as u64).to_le_bytes());
+    bytes.extend((blocks.len() as u64).to_le_bytes());
+    bytes.extend(set.len().to_le_bytes());
+    bytes.extend(skip_table);
+    bytes.extend(encoded);
+
+    bytes
+}
+
+/// An index read from a file, or anything else that can seek, a block at a
+/// time. Only the header and the skip table are kept in memory.
+#[derive(Debug)]
+pub struct IndexReader<R> {
+    source: R,
+    ranges: usize,
+    covered: u128,
+    /// First start and offset of every block
+    skip_table: Vec<(u128, u64)>,
+    /// Where the blocks begin in `source`, and how many bytes they take
+    blocks_start: u64,
+    blocks_len: u64,
+    /// Bytes of the last block read, kept to not allocate on every lookup
+    buffer: Vec<u8>,
+}
+
+impl<R: Read + Seek> IndexReader<R> {
+    pub fn new(mut source: R) -> anyhow::Result<Self> {
+        let mut header = [0; HEADER];
+        source
+            .read_exact(&mut header)
+            .ok()
+            .filter(|_| &header[..MAGIC.len()] == MAGIC)
+            .context("Not a day 5 index")?;
+
+        let read_u64 = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let ranges = read_u64(MAGIC.len()) as usize;
+        let blocks = read_u64(MAGIC.len() + 8) as usize;
+        let covered = u128::from_le_bytes(header[MAGIC.len() + 16..].try_into().unwrap());
+
+        if blocks != ranges.div_ceil(BLOCK) {
+            bail!("Index has {} ranges but {} blocks", ranges, blocks);
+        }
+
+        let blocks_start = blocks
+            .checked_mul(SKIP_ENTRY)
+            .and_then(|size| size.checked_add(HEADER))
+            .context("Index is too short for its skip table")? as u64;
+        let blocks_len = source
+            .seek(SeekFrom::End(0))?
+            .checked_sub(blocks_start)
+            .context("Index is too short for its skip table")?;
+
+        let mut skip_table = vec![0; blocks * SKIP_ENTRY];
+        source.seek(SeekFrom::Start(HEADER as u64))?;
+        source.read_exact(&mut skip_table)?;
+        let skip_table = skip_table
+            .chunks(SKIP_ENTRY)
+            .map(|entry| {
+                let first = u128::from_le_bytes(entry[..16].try_into().unwrap());
+                let offset = u64::from_le_bytes(entry[16..].try_into().unwrap());
+                (first, offset)
+            })
+            .collect::<Vec<(u128, u64)>>();
+
+        Ok(IndexReader {
+            source,
+            ranges,
+            covered,
+            skip_table,
+            blocks_start,
+            blocks_len,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Number of ranges, not of IDs
+    pub fn ranges(&self) -> usize {
+        self.ranges
+    }
+
+    /// Number of IDs in all the ranges
+    pub fn covered(&self) -> u128 {
+        self.covered
+    }
+
+    /// Ranges in `block`, decoded
+    fn block(&mut self, block: usize) -> anyhow::Result<Vec<RangeInclusive<u128>>> {
+        let (first, offset) = self.skip_table[block];
+        // Blocks end where the next one starts
+        let end = self
+            .skip_table
+            .get(block + 1)
+            .map_or(self.blocks_len, |(_, next)| *next);
+        if offset > end || end > self.blocks_len {
+            bail!("Block starts past the end of the index");
+        }
+
+        self.buffer.resize((end - offset) as usize, 0);
+        self.source
+            .seek(SeekFrom::Start(self.blocks_start + offset))?;
+        self.source.read_exact(&mut self.buffer)?;
+
+        let count = BLOCK.min(self.ranges - block * BLOCK);
+        let mut bytes = &self.buffer[..];
+        let mut ranges = Vec::with_capacity(count);
+        let mut previous = first;
+        for _ in 0..count {
+            let (gap, used) = read_varint(bytes)?;
+            bytes = &bytes[used..];
+            let (length, used) = read_varint(bytes)?;
+            bytes = &bytes[used..];
+
+            let start = previous
+                .checked_add(gap)
+                .context("Range in the index overflows")?;
+            let end = start
+                .checked_add(length)
+                .context("Range in the index overflows")?;
+            ranges.push(start..=end);
+            previous = end;
+        }
+
+        Ok(ranges)
+    }
+
+    pub fn contains(&mut self, id: u128) -> anyhow::Result<bool> {
+        // Blocks starting at or before the ID, the last of them is the only
+        // one that can hold it
+        match self.skip_table.partition_point(|(first, _)| *first <= id) {
+            0 => Ok(false),
+            block => Ok(self
+                .block(block - 1)?
+                .iter()
+                .any(|range| range.contains(&id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Seek};
+
+    use crate::{
+        interval_index::{IndexReader, encode_index, read_varint, write_varint},
+        interval_set::IntervalSet,
+    };
+
+    /// Every range in the index, back into a set
+    fn decode<R: Read + Seek>(index: &mut IndexReader<R>) -> IntervalSet<u128> {
+        let mut set = IntervalSet::new();
+        for block in 0..index.skip_table.len() {
+            set.extend(index.block(block).unwrap());
+        }
+
+        set
+    }
+
+    #[test]
+    fn test_varints() {
+        for value in [0, 1, 127, 128, 300, u64::MAX as u128, u128::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(read_varint(&bytes).unwrap(), (value, bytes.len()));
+            assert!(read_varint(&bytes[..bytes.len() - 1]).is_err());
+        }
+
+        assert!(read_varint(&[0xff; 19]).is_err());
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        // Enough ranges to need a few blocks, with the last one reaching u128::MAX
+        let mut set = (0..200u128)
+            .map(|k| k * k * 1000..=k * k * 1000 + k)
+            .collect::<IntervalSet<u128>>();
+        set.insert(u128::MAX - 5..=u128::MAX);
+
+        let mut index = IndexReader::new(Cursor::new(encode_index(&set))).unwrap();
+        assert_eq!(index.ranges(), 201);
+        assert_eq!(index.covered(), set.len());
+        assert_eq!(decode(&mut index), set);
+
+        for id in [
+            0,
+            1,
+            999,
+            1000,
+            1001,
+            4002,
+            4003,
+            39_601_000,
+            39_601_199,
+            u128::MAX,
+        ] {
+            assert_eq!(index.contains(id).unwrap(), set.contains(id), "ID {}", id);
+        }
+
+        let mut index = IndexReader::new(Cursor::new(encode_index(&IntervalSet::new()))).unwrap();
+        assert!(!index.contains(0).unwrap());
+        assert_eq!(index.covered(), 0);
+        assert!(decode(&mut index).is_empty());
+    }
+
+    #[test]
+    fn test_index_rejects_bad_bytes() {
+        let bytes = encode_index(&IntervalSet::from_iter([3..=5, 10..=20]));
+
+        assert!(IndexReader::new(Cursor::new(b"not an index at all")).is_err());
+        assert!(IndexReader::new(Cursor::new(&bytes[..50])).is_err());
+
+        let mut truncated = IndexReader::new(Cursor::new(&bytes[..bytes.len() - 1])).unwrap();
+        assert!(truncated.contains(12).is_err());
+    }
+}
@@ -0,0 +1,779 @@
+//! Sorted list of disjoint inclusive ranges, shared by the days that deal with
+//! big spans of IDs.
+//!
+//! Ranges are kept sorted by their start, and overlapping or adjacent ranges
+//! are merged as they are inserted, so every set has a single way to be
+//! written down and lookups are a binary search.
+
+use std::{fmt, ops::RangeInclusive};
+
+/// Integers an `IntervalSet` can hold
+pub trait Integer: Copy + Ord + fmt::Debug {
+    /// Number of values in `start..=end`, saturating for the few ranges that
+    /// span every value of a 128 bit type
+    fn count(start: Self, end: Self) -> u128;
+
+    const MIN: Self;
+    const MAX: Self;
+
+    fn predecessor(self) -> Self;
+    fn successor(self) -> Self;
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                const MIN: Self = <$t>::MIN;
+                const MAX: Self = <$t>::MAX;
+
+                fn count(start: Self, end: Self) -> u128 {
+                    // Sign extending keeps the difference right modulo 2^128
+                    (end as u128).wrapping_sub(start as u128).saturating_add(1)
+                }
+
+                fn predecessor(self) -> Self {
+                    self - 1
+                }
+
+                fn successor(self) -> Self {
+                    self + 1
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+
+/// Where a value falls in the list of ranges
+#[derive(Debug, PartialEq)]
+pub enum SearchResult {
+    /// Index of the range that contains it
+    Inside(usize),
+    /// Index the value would be inserted at
+    Outside(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalSet<T> {
+    ranges: Vec<RangeInclusive<T>>,
+}
+
+impl<T> Default for IntervalSet<T> {
+    fn default() -> Self {
+        IntervalSet { ranges: Vec::new() }
+    }
+}
+
+impl<T: Integer> IntervalSet<T> {
+    pub fn new() -> Self {
+        IntervalSet::default()
+    }
+
+    /// Index of the first range that ends at or after `value`, so the only
+    /// one that could contain it. The number of ranges when there's none.
+    pub fn lower_bound(&self, value: T) -> usize {
+        self.ranges.partition_point(|range| *range.end() < value)
+    }
+
+    /// Index of the first range that starts after `value`. Every range before
+    /// it starts at or before `value`.
+    pub fn upper_bound(&self, value: T) -> usize {
+        self.ranges.partition_point(|range| *range.start() <= value)
+    }
+
+    pub fn find(&self, value: T) -> SearchResult {
+        let i = self.lower_bound(value);
+
+        match self.ranges.get(i) {
+            Some(range) if *range.start() <= value => SearchResult::Inside(i),
+            _ => SearchResult::Outside(i),
+        }
+    }
+
+    /// Adds every value of `range`, merging it with the ranges it overlaps or
+    /// touches, so `3..=5` and `6..=8` end up as `3..=8`. Empty ranges add
+    /// nothing.
+    pub fn insert(&mut self, range: RangeInclusive<T>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (start, end) = range.into_inner();
+
+        // Ranges first..last overlap or touch the new one, and all become one.
+        // Values right next to it count too, unless they'd be past the type's ends
+        let first = match start {
+            start if start == T::MIN => 0,
+            start => self.lower_bound(start.predecessor()),
+        };
+        let last = match end {
+            end if end == T::MAX => self.ranges.len(),
+            end => self.upper_bound(end.successor()),
+        };
+
+        let merged = if first < last {
+            start.min(*self.ranges[first].start())..=end.max(*self.ranges[last - 1].end())
+        } else {
+            start..=end
+        };
+
+        self.ranges.splice(first..last, [merged]);
+    }
+
+    /// Takes every value of `range` out of the set, splitting the ranges it
+    /// only partly covers
+    pub fn remove(&mut self, range: RangeInclusive<T>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (start, end) = range.into_inner();
+        // Ranges first..last are the ones that overlap `range`
+        let first = self.lower_bound(start);
+        let last = self.upper_bound(end);
+        if first >= last {
+            return;
+        }
+
+        let mut pieces = Vec::with_capacity(2);
+        let (left, right) = (*self.ranges[first].start(), *self.ranges[last - 1].end());
+        if left < start {
+            pieces.push(left..=start.predecessor());
+        }
+        if right > end {
+            pieces.push(end.successor()..=right);
+        }
+
+        self.ranges.splice(first..last, pieces);
+    }
+
+    pub fn union(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        let mut ranges: Vec<RangeInclusive<T>> = Vec::new();
+        let (mut i, mut j) = (0, 0);
+
+        loop {
+            // Ranges of both sets, lowest start first
+            let next = match (self.ranges.get(i), other.ranges.get(j)) {
+                (Some(a), Some(b)) if b.start() < a.start() => {
+                    j += 1;
+                    b
+                }
+                (Some(a), _) => {
+                    i += 1;
+                    a
+                }
+                (None, Some(b)) => {
+                    j += 1;
+                    b
+                }
+                (None, None) => break,
+            };
+
+            // Merged into the last range when they overlap or touch, like `insert` does
+            match ranges.last_mut() {
+                Some(last) if *last.end() == T::MAX || *next.start() <= last.end().successor() => {
+                    if next.end() > last.end() {
+                        *last = *last.start()..=*next.end();
+                    }
+                }
+                _ => ranges.push(next.clone()),
+            }
+        }
+
+        IntervalSet { ranges }
+    }
+
+    pub fn intersection(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (&self.ranges[i], &other.ranges[j]);
+            let start = *a.start().max(b.start());
+            let end = *a.end().min(b.end());
+            if start <= end {
+                ranges.push(start..=end);
+            }
+
+            // Whichever ends first can't overlap anything else in the other set
+            if a.end() < b.end() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        IntervalSet { ranges }
+    }
+
+    /// Values in this set that aren't in `other`
+    pub fn difference(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        let mut ranges = Vec::new();
+        let mut j = 0;
+
+        for range in self.ranges.iter() {
+            let (mut start, end) = (*range.start(), *range.end());
+
+            // Ranges of `other` ending before this one can't cut any later one either
+            while other.ranges.get(j).is_some_and(|cut| *cut.end() < start) {
+                j += 1;
+            }
+
+            // Cuts that start inside the range, taking out a piece at a time.
+            // The last one can reach into the next range, so it's kept around
+            let mut covered = false;
+            while let Some(cut) = other.ranges.get(j).filter(|cut| *cut.start() <= end) {
+                if *cut.start() > start {
+                    ranges.push(start..=cut.start().predecessor());
+                }
+                if *cut.end() >= end {
+                    covered = true;
+                    break;
+                }
+
+                start = cut.end().successor();
+                j += 1;
+            }
+
+            if !covered {
+                ranges.push(start..=end);
+            }
+        }
+
+        IntervalSet { ranges }
+    }
+
+    /// Values in `bounds` that aren't in the set
+    pub fn complement(&self, bounds: RangeInclusive<T>) -> IntervalSet<T> {
+        IntervalSet::from_iter([bounds]).difference(self)
+    }
+
+    /// Lowest and highest value in the set
+    pub fn bounds(&self) -> Option<RangeInclusive<T>> {
+        match (self.ranges.first(), self.ranges.last()) {
+            (Some(first), Some(last)) => Some(*first.start()..=*last.end()),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        matches!(self.find(value), SearchResult::Inside(_))
+    }
+
+    /// Whether each of `values` is in the set, in the order they were given.
+    ///
+    /// Instead of a binary search per value, the values are sorted and walked
+    /// alongside the ranges in one go, which pays off once there are many.
+    pub fn contains_many(&self, values: &[T]) -> Vec<bool> {
+        let mut order = (0..values.len()).collect::<Vec<usize>>();
+        order.sort_unstable_by_key(|k| values[*k]);
+
+        let mut membership = vec![false; values.len()];
+        let mut ranges = self.ranges.iter().peekable();
+        for k in order {
+            // Values only go up, so ranges ending before this one are done with
+            while ranges.next_if(|range| *range.end() < values[k]).is_some() {}
+
+            membership[k] = ranges
+                .peek()
+                .is_some_and(|range| range.contains(&values[k]));
+        }
+
+        membership
+    }
+
+    /// Number of values covered by the ranges
+    pub fn len(&self) -> u128 {
+        self.ranges
+            .iter()
+            .map(|range| T::count(*range.start(), *range.end()))
+            .fold(0, u128::saturating_add)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Range at `index`, as given by `find`
+    pub fn get(&self, index: usize) -> Option<&RangeInclusive<T>> {
+        self.ranges.get(index)
+    }
+
+    /// Ranges in the set, from lowest to highest
+    pub fn iter(&self) -> impl Iterator<Item = &RangeInclusive<T>> {
+        self.ranges.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a IntervalSet<T> {
+    type Item = &'a RangeInclusive<T>;
+    type IntoIter = std::slice::Iter<'a, RangeInclusive<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.iter()
+    }
+}
+
+impl<T: Integer> Extend<RangeInclusive<T>> for IntervalSet<T> {
+    fn extend<I: IntoIterator<Item = RangeInclusive<T>>>(&mut self, ranges: I) {
+        for range in ranges {
+            self.insert(range);
+        }
+    }
+}
+
+impl<T: Integer> FromIterator<RangeInclusive<T>> for IntervalSet<T> {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<T>>>(ranges: I) -> Self {
+        let mut set = IntervalSet::new();
+        set.extend(ranges);
+        set
+    }
+}
+
+/// Every range as it was given, next to their merged view, to tell how many
+/// of them cover each value.
+///
+/// Ranges carry a label, like the line they were read from, to tell them apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlapIndex<T> {
+    /// Sorted by start
+    ranges: Vec<(RangeInclusive<T>, usize)>,
+    /// Highest end among `ranges[..=k]`, so lookups know when to stop looking back
+    max_ends: Vec<T>,
+    /// Where the number of covering ranges changes, and what it changes to,
+    /// found sweeping over the starts and ends
+    depths: Vec<(T, usize)>,
+    merged: IntervalSet<T>,
+}
+
+impl<T: Integer> OverlapIndex<T> {
+    pub fn new(ranges: impl IntoIterator<Item = (RangeInclusive<T>, usize)>) -> Self {
+        let mut ranges = ranges
+            .into_iter()
+            .filter(|(range, _)| !range.is_empty())
+            .collect::<Vec<(RangeInclusive<T>, usize)>>();
+        ranges.sort_by_key(|(range, label)| (*range.start(), *label));
+
+        let max_ends = ranges
+            .iter()
+            .scan(None, |max_end: &mut Option<T>, (range, _)| {
+                let end = max_end.map_or(*range.end(), |max_end| max_end.max(*range.end()));
+                *max_end = Some(end);
+                Some(end)
+            })
+            .collect();
+
+        // A range stops counting right after its end, which doesn't exist for
+        // ranges that go up to the last value
+        let mut events = Vec::with_capacity(ranges.len() * 2);
+        for (range, _) in ranges.iter() {
+            events.push((*range.start(), 1));
+            if *range.end() != T::MAX {
+                events.push((range.end().successor(), -1));
+            }
+        }
+        events.sort();
+
+        let mut depths: Vec<(T, usize)> = Vec::new();
+        let mut depth = 0usize;
+        for (k, (position, change)) in events.iter().enumerate() {
+            depth = depth.wrapping_add_signed(*change);
+
+            let last_at_position = events.get(k + 1).is_none_or(|(next, _)| next != position);
+            let changed = depths.last().map_or(0, |(_, last)| *last) != depth;
+            if last_at_position && changed {
+                depths.push((*position, depth));
+            }
+        }
+
+        let merged = ranges.iter().map(|(range, _)| range.clone()).collect();
+
+        OverlapIndex {
+            ranges,
+            max_ends,
+            depths,
+            merged,
+        }
+    }
+
+    /// Number of ranges that contain `value`
+    pub fn depth(&self, value: T) -> usize {
+        match self
+            .depths
+            .partition_point(|(position, _)| *position <= value)
+        {
+            0 => 0,
+            k => self.depths[k - 1].1,
+        }
+    }
+
+    /// Labels of the ranges that contain `value`, lowest first
+    pub fn containing(&self, value: T) -> Vec<usize> {
+        let candidates = self
+            .ranges
+            .partition_point(|(range, _)| *range.start() <= value);
+
+        let mut labels = (0..candidates)
+            .rev()
+            .take_while(|k| self.max_ends[*k] >= value)
+            .filter(|k| *self.ranges[*k].0.end() >= value)
+            .map(|k| self.ranges[k].1)
+            .collect::<Vec<usize>>();
+        labels.sort();
+
+        labels
+    }
+
+    /// Most ranges covering any one value
+    pub fn max_depth(&self) -> usize {
+        self.depths
+            .iter()
+            .map(|(_, depth)| *depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn merged(&self) -> &IntervalSet<T> {
+        &self.merged
+    }
+}
+
+impl<T: Integer> FromIterator<(RangeInclusive<T>, usize)> for OverlapIndex<T> {
+    fn from_iter<I: IntoIterator<Item = (RangeInclusive<T>, usize)>>(ranges: I) -> Self {
+        OverlapIndex::new(ranges)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, ops::RangeInclusive};
+
+    use crate::{
+        interval_set::{Integer, IntervalSet, OverlapIndex, SearchResult},
+        xorshift::XorShift,
+    };
+
+    fn ranges<T: Clone>(set: &IntervalSet<T>) -> Vec<RangeInclusive<T>> {
+        set.into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_find() {
+        let set = IntervalSet::from_iter([3..=5, 7..=9, 11..=15, 17..=20]);
+
+        assert_eq!(set.find(4), SearchResult::Inside(0));
+        assert_eq!(set.find(8), SearchResult::Inside(1));
+        assert_eq!(set.find(12), SearchResult::Inside(2));
+        assert_eq!(set.find(17), SearchResult::Inside(3));
+        assert_eq!(set.find(21), SearchResult::Outside(4));
+        assert_eq!(set.find(0), SearchResult::Outside(0));
+        assert_eq!(set.find(10), SearchResult::Outside(2));
+    }
+
+    #[test]
+    fn test_find_edge_cases() {
+        let empty = IntervalSet::<u128>::new();
+        assert_eq!(empty.find(0), SearchResult::Outside(0));
+        assert_eq!(empty.find(u128::MAX), SearchResult::Outside(0));
+        assert_eq!(empty.lower_bound(7), 0);
+        assert_eq!(empty.upper_bound(7), 0);
+
+        let set = IntervalSet::from_iter([3..=5, 7..=7, 10..=u128::MAX]);
+        assert_eq!(set.find(3), SearchResult::Inside(0));
+        assert_eq!(set.find(5), SearchResult::Inside(0));
+        assert_eq!(set.find(6), SearchResult::Outside(1));
+        assert_eq!(set.find(7), SearchResult::Inside(1));
+        assert_eq!(set.find(8), SearchResult::Outside(2));
+        assert_eq!(set.find(10), SearchResult::Inside(2));
+        assert_eq!(set.find(u128::MAX), SearchResult::Inside(2));
+
+        // Bounds on the edges of a range
+        assert_eq!((set.lower_bound(5), set.upper_bound(5)), (0, 1));
+        assert_eq!((set.lower_bound(3), set.upper_bound(3)), (0, 1));
+        assert_eq!((set.lower_bound(2), set.upper_bound(2)), (0, 0));
+        assert_eq!((set.lower_bound(6), set.upper_bound(6)), (1, 1));
+        assert_eq!(
+            (set.lower_bound(u128::MAX), set.upper_bound(u128::MAX)),
+            (2, 3)
+        );
+
+        let set = IntervalSet::from_iter([u128::MAX..=u128::MAX]);
+        assert_eq!(set.find(u128::MAX), SearchResult::Inside(0));
+        assert_eq!(set.find(u128::MAX - 1), SearchResult::Outside(0));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut set = IntervalSet::from_iter([3..=5]);
+
+        set.insert(4..=6);
+        assert_eq!(ranges(&set), vec![3..=6]);
+        set.insert(1..=1);
+        assert_eq!(ranges(&set), vec![1..=1, 3..=6]);
+        set.insert(8..=9);
+        assert_eq!(ranges(&set), vec![1..=1, 3..=6, 8..=9]);
+        set.insert(7..=7);
+        assert_eq!(ranges(&set), vec![1..=1, 3..=9]);
+        set.insert(0..=10);
+        assert_eq!(ranges(&set), vec![0..=10]);
+        set.insert(12..=14);
+        set.insert(14..=18);
+        assert_eq!(ranges(&set), vec![0..=10, 12..=18]);
+        set.insert(13..=19);
+        assert_eq!(ranges(&set), vec![0..=10, 12..=19]);
+
+        set.insert(20..=21);
+        assert_eq!(ranges(&set), vec![0..=10, 12..=21]);
+        set.insert(11..=11);
+        assert_eq!(ranges(&set), vec![0..=21]);
+
+        #[allow(clippy::reversed_empty_ranges)]
+        set.insert(30..=20);
+        assert_eq!(ranges(&set), vec![0..=21]);
+
+        let mut set = IntervalSet::from_iter([u8::MIN..=0, 2..=3, u8::MAX..=u8::MAX]);
+        set.insert(1..=1);
+        set.insert(4..=254);
+        assert_eq!(ranges(&set), vec![0..=u8::MAX]);
+
+        let mut set = IntervalSet::from_iter([5..=6, 10..=12]);
+        set.insert(0..=11);
+        assert_eq!(ranges(&set), vec![0..=12]);
+    }
+
+    /// Sorted, and at least one value between each range and the next
+    fn assert_canonical<T: Integer>(set: &IntervalSet<T>) {
+        for range in set {
+            assert!(!range.is_empty(), "Empty range in {:?}", set);
+        }
+        for pair in set.ranges.windows(2) {
+            assert!(
+                T::count(*pair[0].end(), *pair[1].start()) > 2 && pair[0].end() < pair[1].start(),
+                "Ranges {:?} and {:?} should have been merged",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_and_remove_match_a_set_of_every_value() {
+        let mut rng = XorShift::new(0x1f83d9abfb41bd6b);
+
+        for _ in 0..200 {
+            let mut set = IntervalSet::new();
+            let mut model = BTreeSet::new();
+
+            for _ in 0..(rng.next_u64() % 30) {
+                // Values near both ends of the type, where successors overflow
+                let start = (rng.next_u64() % 256) as u8;
+                let end = start.saturating_add((rng.next_u64() % 12) as u8);
+
+                if rng.next_u64().is_multiple_of(4) {
+                    set.remove(start..=end);
+                    for value in start..=end {
+                        model.remove(&value);
+                    }
+                } else {
+                    set.insert(start..=end);
+                    model.extend(start..=end);
+                }
+
+                assert_canonical(&set);
+                assert_eq!(set.len(), model.len() as u128);
+            }
+
+            for value in u8::MIN..=u8::MAX {
+                assert_eq!(set.contains(value), model.contains(&value));
+            }
+
+            // Both sets are canonical, so rebuilding it from its values gives
+            // back exactly the same ranges
+            let rebuilt = model
+                .iter()
+                .map(|value| *value..=*value)
+                .collect::<IntervalSet<u8>>();
+            assert_eq!(rebuilt, set);
+        }
+    }
+
+    #[test]
+    fn test_contains_and_len_over_other_integers() {
+        let set: IntervalSet<i32> = [-10..=-5, 0..=0, -7..=2].into_iter().collect();
+        assert_eq!(ranges(&set), vec![-10..=2]);
+        assert_eq!(set.len(), 13);
+        assert!(set.contains(-10));
+        assert!(!set.contains(3));
+
+        let set = IntervalSet::from_iter([0..=u8::MAX]);
+        assert_eq!(set.len(), 256);
+
+        let set = IntervalSet::from_iter([i128::MIN..=i128::MAX]);
+        assert_eq!(set.len(), u128::MAX);
+        assert!(IntervalSet::<u64>::new().is_empty());
+    }
+
+    #[test]
+    fn test_contains_many() {
+        let set = IntervalSet::from_iter([3..=5, 7..=9, 11..=15, 17..=u64::MAX]);
+        let values = [21, 0, 3, 10, 9, 6, 15, u64::MAX, 16, 3];
+
+        let expected = values
+            .iter()
+            .map(|value| set.contains(*value))
+            .collect::<Vec<bool>>();
+        assert_eq!(set.contains_many(&values), expected);
+        assert_eq!(
+            expected,
+            vec![
+                true, false, true, false, true, false, true, true, false, true
+            ]
+        );
+
+        assert!(
+            IntervalSet::<u64>::new()
+                .contains_many(&values)
+                .iter()
+                .all(|fresh| !fresh)
+        );
+        assert!(set.contains_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut set = IntervalSet::from_iter([0..=10, 20..=30, 40..=50]);
+
+        set.remove(5..=5);
+        assert_eq!(ranges(&set), vec![0..=4, 6..=10, 20..=30, 40..=50]);
+        set.remove(8..=45);
+        assert_eq!(ranges(&set), vec![0..=4, 6..=7, 46..=50]);
+        set.remove(11..=40);
+        assert_eq!(ranges(&set), vec![0..=4, 6..=7, 46..=50]);
+        set.remove(0..=4);
+        assert_eq!(ranges(&set), vec![6..=7, 46..=50]);
+
+        let mut set = IntervalSet::from_iter([0..=u8::MAX]);
+        set.remove(u8::MAX..=u8::MAX);
+        set.remove(0..=0);
+        assert_eq!(ranges(&set), vec![1..=254]);
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let a = IntervalSet::from_iter([0..=10, 20..=30]);
+        let b = IntervalSet::from_iter([5..=25, 28..=40]);
+
+        assert_eq!(ranges(&a.union(&b)), vec![0..=40]);
+        assert_eq!(ranges(&a.intersection(&b)), vec![5..=10, 20..=25, 28..=30]);
+        assert_eq!(ranges(&a.difference(&b)), vec![0..=4, 26..=27]);
+        assert_eq!(ranges(&b.difference(&a)), vec![11..=19, 31..=40]);
+        assert_eq!(ranges(&a.complement(0..=35)), vec![11..=19, 31..=35]);
+        assert_eq!(a.bounds(), Some(0..=30));
+
+        // Every value ends up in exactly one of the pieces
+        let either = a.union(&b);
+        let pieces = a.difference(&b).len() + b.difference(&a).len() + a.intersection(&b).len();
+        assert_eq!(either.len(), pieces);
+        assert!(either.complement(0..=40).is_empty());
+    }
+
+    #[test]
+    fn test_set_algebra_matches_a_set_of_every_value() {
+        let mut rng = XorShift::new(0x5be0cd19137e2179);
+
+        // Random ranges over all of u8, so successors overflow at the ends
+        let mut random_set = || {
+            (0..(rng.next_u64() % 12))
+                .map(|_| {
+                    let start = (rng.next_u64() % 256) as u8;
+                    start..=start.saturating_add((rng.next_u64() % 40) as u8)
+                })
+                .collect::<IntervalSet<u8>>()
+        };
+        let values = |set: &IntervalSet<u8>| {
+            (u8::MIN..=u8::MAX)
+                .filter(|value| set.contains(*value))
+                .collect::<BTreeSet<u8>>()
+        };
+
+        for _ in 0..300 {
+            let (a, b) = (random_set(), random_set());
+            let (a_values, b_values) = (values(&a), values(&b));
+
+            let union = a.union(&b);
+            assert_canonical(&union);
+            assert_eq!(values(&union), &a_values | &b_values);
+
+            let difference = a.difference(&b);
+            assert_canonical(&difference);
+            assert_eq!(values(&difference), &a_values - &b_values);
+
+            let complement = a.complement(u8::MIN..=u8::MAX);
+            assert_canonical(&complement);
+            assert_eq!(complement.len(), 256 - a_values.len() as u128);
+        }
+    }
+
+    #[test]
+    fn test_overlap_index() {
+        let index = OverlapIndex::from_iter([
+            (10..=20, 1),
+            (15..=25, 2),
+            (18..=18, 3),
+            (30..=u8::MAX, 4),
+            (0..=40, 5),
+        ]);
+
+        assert_eq!(index.depth(5), 1);
+        assert_eq!(index.depth(14), 2);
+        assert_eq!(index.depth(18), 4);
+        assert_eq!(index.depth(26), 1);
+        assert_eq!(index.depth(35), 2);
+        assert_eq!(index.depth(u8::MAX), 1);
+        assert_eq!(index.max_depth(), 4);
+
+        assert_eq!(index.containing(18), vec![1, 2, 3, 5]);
+        assert_eq!(index.containing(21), vec![2, 5]);
+        assert_eq!(index.containing(100), vec![4]);
+        assert_eq!(ranges(index.merged()), vec![0..=u8::MAX]);
+
+        let empty = OverlapIndex::<u32>::from_iter([]);
+        assert_eq!(empty.max_depth(), 0);
+        assert_eq!(empty.depth(0), 0);
+        assert!(empty.containing(0).is_empty());
+    }
+
+    #[test]
+    fn test_overlap_index_matches_counting() {
+        let mut rng = XorShift::new(0x510e527fade682d1);
+
+        for _ in 0..50 {
+            let ranges = (0..(rng.next_u64() % 10) as usize)
+                .map(|label| {
+                    let start = (rng.next_u64() % 60) as u32;
+                    (start..=start + (rng.next_u64() % 20) as u32, label)
+                })
+                .collect::<Vec<(RangeInclusive<u32>, usize)>>();
+            let index = OverlapIndex::from_iter(ranges.clone());
+
+            let mut max_depth = 0;
+            for value in 0..85 {
+                let expected = ranges
+                    .iter()
+                    .filter(|(range, _)| range.contains(&value))
+                    .map(|(_, label)| *label)
+                    .collect::<Vec<usize>>();
+
+                assert_eq!(index.depth(value), expected.len());
+                assert_eq!(index.containing(value), expected);
+                assert_eq!(index.merged().contains(value), !expected.is_empty());
+                max_depth = max_depth.max(expected.len());
+            }
+            assert_eq!(index.max_depth(), max_depth);
+        }
+    }
+}
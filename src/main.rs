@@ -1,10 +1,13 @@
 use std::{
-    path::Path,
-    time::{SystemTime, UNIX_EPOCH},
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::bail;
-use clap::{Parser, ValueEnum, command};
+use anyhow::{Context, bail};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use crate::{
     d01_a_password::{
@@ -13,9 +16,26 @@ use crate::{
     d02_gift_shop::{
         find_invalid_ids_of_ranges, is_repeating_sequence, is_sequence_twice, read_ranges,
     },
-    d03_lobby::{find_joltage_in_battery_packs, find_max_joltage, read_battery_packs},
-    d04_printing::{get_accessable_rolls, get_accessable_rolls_with_removal, read_rolls},
-    d05_ingredients::{find_valid_ids, get_total_fresh, read_ids},
+    d03_lobby::{
+        Constraints, DigitAlphabet, find_activations_in_battery_packs,
+        find_joltage_in_battery_packs, find_max_subsequence, find_max_subsequence_constrained,
+        find_max_subsequence_stack, find_min_subsequence, find_top_subsequences,
+        read_battery_packs, render_activations,
+    },
+    d04_printing::{
+        Boundary, Cell, Comparison, Neighborhood, Round, Rule, Symbols, Warehouse,
+        get_accessable_rolls, get_accessable_rolls_bits, get_accessable_rolls_with_removal,
+        get_accessable_rolls_with_removal_bits, get_neighbor_counts, get_removal_rounds,
+        get_roll_statistics, parse_rolls_bits, parse_rolls_sparse, read_rolls, render_heatmap,
+        write_rolls,
+    },
+    d05_ingredients::{
+        audit_ids, find_valid_ids, find_valid_ids_merged, get_total_fresh, read_ids,
+        read_ids_split, render_audit, run_command,
+    },
+    interval_index::{IndexReader, encode_index},
+    interval_set::IntervalSet,
+    xorshift::XorShift,
 };
 
 mod d01_a_password;
@@ -23,6 +43,10 @@ mod d02_gift_shop;
 mod d03_lobby;
 mod d04_printing;
 mod d05_ingredients;
+mod grid;
+mod interval_index;
+mod interval_set;
+mod xorshift;
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
 enum ProblemPart {
@@ -30,9 +54,72 @@ enum ProblemPart {
     Two,
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Solver {
+    Table,
+    Stack,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Objective {
+    Max,
+    Min,
+    MinNoLeadingZero,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Backend {
+    Dense,
+    Bits,
+    Sparse,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum LookupStrategy {
+    BinarySearch,
+    MergeJoin,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Work with a prebuilt index of the day 5 fresh ranges
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+    /// Load a day 5 database once, then check and edit its ranges from stdin
+    Repl {
+        #[arg(long, default_value = "./data/day-5/long.txt")]
+        input: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum IndexAction {
+    /// Merge the ranges of a day 5 database and write them as an index
+    Build {
+        #[arg(long, default_value = "./data/day-5/long.txt")]
+        input: PathBuf,
+
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Tell whether each ID is fresh, from an index
+    Query {
+        #[arg(long)]
+        index: PathBuf,
+
+        #[arg(required = true)]
+        ids: Vec<u128>,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     // Day from advent of code to run. Days are 1-index.
     #[arg(long, default_value_t = 1)]
     day: u16,
@@ -40,6 +127,128 @@ struct Args {
     // Part of the problem. There are two per day (AFAIK)
     #[arg(long, value_enum, default_value_t = ProblemPart::One)]
     part: ProblemPart,
+
+    // Symbols the day 3 battery packs are written with, lowest value first
+    #[arg(long, default_value = "0123456789")]
+    alphabet: String,
+
+    // Algorithm used to pick the day 3 batteries. Both give the same answer
+    #[arg(long, value_enum, default_value_t = Solver::Stack)]
+    solver: Solver,
+
+    // Print every day 3 pack with its activated batteries highlighted
+    #[arg(long)]
+    explain: bool,
+
+    // Batteries to turn on per day 3 pack. Defaults to 2 for part one and 12 for part two
+    #[arg(long)]
+    activations: Option<usize>,
+
+    // Batteries that must stay off between two activated ones. Ignores --solver
+    #[arg(long, default_value_t = 0)]
+    min_gap: usize,
+
+    // Index of the battery that must be turned on first. Ignores --solver
+    #[arg(long)]
+    first_battery: Option<usize>,
+
+    // Whether day 3 looks for the biggest or the smallest joltage per pack
+    #[arg(long, value_enum, default_value_t = Objective::Max)]
+    objective: Objective,
+
+    // Also list the N biggest distinct joltages of every day 3 pack
+    #[arg(long)]
+    top: Option<usize>,
+
+    // Cells around a day 4 roll that count as neighbors: moore[:r], von-neumann[:r]
+    // or custom:di,dj;di,dj
+    #[arg(long, default_value = "moore")]
+    neighborhood: Neighborhood,
+
+    // Number of neighbors a day 4 roll is compared against
+    #[arg(long, default_value_t = 4)]
+    threshold: usize,
+
+    // How a day 4 roll's neighbors compare to the threshold: <, <=, =, >=, >
+    #[arg(long, default_value = "<")]
+    comparison: Comparison,
+
+    // What lies past the edges of the day 4 warehouse: empty, wrap or roll
+    #[arg(long, default_value = "empty")]
+    boundary: Boundary,
+
+    // Print the day 4 warehouse after every round, marking the removed rolls with x
+    #[arg(long)]
+    animate: bool,
+
+    // Print the neighbor count of every day 4 roll and some statistics about them
+    #[arg(long)]
+    heatmap: bool,
+
+    // How the day 4 warehouse is stored. bits only supports the moore neighborhood
+    // and can't animate or draw it, sparse only keeps the rolls around
+    #[arg(long, value_enum, default_value_t = Backend::Dense)]
+    backend: Backend,
+
+    // Drop the edges of the day 4 warehouse, so it goes on forever. Needs the sparse backend
+    #[arg(long)]
+    unbounded: bool,
+
+    // Characters that stand for an empty cell in the day 4 input
+    #[arg(long, default_value = ".")]
+    empty_symbols: String,
+
+    // Characters that stand for a roll in the day 4 input
+    #[arg(long, default_value = "@")]
+    roll_symbols: String,
+
+    // Characters that stand for a roll about to be removed in the day 4 input. It's still a roll
+    #[arg(long, default_value = "x")]
+    to_remove_symbols: String,
+
+    // Treat whitespace around the day 4 lines as cells instead of ignoring it
+    #[arg(long)]
+    keep_whitespace: bool,
+
+    // Write the day 4 warehouse, as it's left, to this file
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    // Another day 5 database to compare the fresh IDs against
+    #[arg(long)]
+    compare: Option<PathBuf>,
+
+    // List the day 5 IDs, up to the highest fresh one, that no range covers
+    #[arg(long)]
+    gaps: bool,
+
+    // Print how many day 5 ranges overlap at the deepest point
+    #[arg(long)]
+    overlaps: bool,
+
+    // Day 5 IDs to list every range that contains, by line number
+    #[arg(long)]
+    lookup: Vec<u128>,
+
+    // How day 5 looks up the IDs: a binary search each, or one pass over them sorted
+    #[arg(long, value_enum, default_value_t = LookupStrategy::BinarySearch)]
+    strategy: LookupStrategy,
+
+    // Time both day 5 lookup strategies on this many made up IDs
+    #[arg(long)]
+    benchmark: Option<usize>,
+
+    // Write every day 5 ID, fresh or spoiled, with the ranges around it to this CSV file
+    #[arg(long)]
+    audit: Option<PathBuf>,
+
+    // Read the day 5 ranges from this file, without any IDs in it, instead of the puzzle input
+    #[arg(long)]
+    ranges: Option<PathBuf>,
+
+    // Read the day 5 IDs from this file, one per line. Needs --ranges
+    #[arg(long, requires = "ranges")]
+    ids: Option<PathBuf>,
 }
 
 fn day_one(part: &ProblemPart) -> anyhow::Result<()> {
@@ -74,16 +283,106 @@ fn day_two(part: &ProblemPart) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn day_three(part: &ProblemPart) -> anyhow::Result<()> {
-    let packs = read_battery_packs(Path::new("./data/day-3/long.txt"));
+fn day_three(part: &ProblemPart, args: &Args) -> anyhow::Result<()> {
+    let alphabet = DigitAlphabet::new(&args.alphabet)?;
+    let packs = read_battery_packs(Path::new("./data/day-3/long.txt"), &alphabet)?;
 
-    let value = match part {
-        ProblemPart::One => {
-            find_joltage_in_battery_packs(&packs, |batteries| find_max_joltage(batteries, 2))
+    let activations = match (args.activations, part) {
+        (Some(0), _) => bail!("At least one battery has to be activated"),
+        (Some(activations), _) => activations,
+        (None, ProblemPart::One) => 2,
+        (None, ProblemPart::Two) => 12,
+    };
+
+    let constraints = Constraints {
+        min_gap: args.min_gap,
+        first_battery: args.first_battery,
+    };
+
+    if constraints != Constraints::default() && !matches!(args.objective, Objective::Max) {
+        bail!("Constraints can only be used when looking for the biggest joltage");
+    }
+
+    let radix = alphabet.radix();
+
+    // The table keeps its values in u64s, the stack builds a Joltage of any size
+    let uses_table = matches!(args.solver, Solver::Table)
+        && matches!(args.objective, Objective::Max)
+        && constraints == Constraints::default();
+    let fits = u32::try_from(activations)
+        .ok()
+        .and_then(|activations| (radix as u64).checked_pow(activations))
+        .is_some();
+    if uses_table && !fits {
+        bail!(
+            "The table solver can't fit {} activations in radix {} in a u64, use --solver stack",
+            activations,
+            radix
+        );
+    }
+
+    // The solvers assert on these, so catch them before any pack is solved
+    if packs.is_empty() {
+        bail!("There are no battery packs");
+    }
+    for (i, batteries) in packs.iter().enumerate() {
+        if activations > batteries.len() {
+            bail!(
+                "Pack {} has {} batteries, can't activate {}",
+                i + 1,
+                batteries.len(),
+                activations
+            );
         }
-        ProblemPart::Two => {
-            find_joltage_in_battery_packs(&packs, |batteries| find_max_joltage(batteries, 12))
+        match args.first_battery {
+            Some(first) if first >= batteries.len() => bail!(
+                "Pack {} has {} batteries, there is no battery {} to start with",
+                i + 1,
+                batteries.len(),
+                first
+            ),
+            _ => (),
+        }
+    }
+
+    let solve = |batteries: &[u8]| match args.objective {
+        Objective::Min => find_min_subsequence(batteries, activations, radix, true)
+            .context("Every pack has a minimum"),
+        Objective::MinNoLeadingZero => find_min_subsequence(batteries, activations, radix, false)
+            .context("No non-zero battery early enough to start with"),
+        Objective::Max if constraints != Constraints::default() => {
+            find_max_subsequence_constrained(batteries, activations, radix, &constraints)
+                .context("No batteries in the pack satisfy the constraints")
+        }
+        Objective::Max => Ok(match args.solver {
+            Solver::Table => find_max_subsequence(batteries, activations, radix),
+            Solver::Stack => find_max_subsequence_stack(batteries, activations, radix),
+        }),
+    };
+
+    if let Some(top) = args.top {
+        for (i, batteries) in packs.iter().enumerate() {
+            let values = find_top_subsequences(batteries, activations, radix, top)
+                .into_iter()
+                .map(|subsequence| subsequence.value.to_string())
+                .collect::<Vec<String>>();
+
+            println!("Pack {}: {}", i + 1, values.join(", "));
         }
+    }
+
+    let value = if args.explain {
+        let activated = find_activations_in_battery_packs(&packs, solve)?;
+        for (batteries, subsequence) in packs.iter().zip(&activated) {
+            println!("{}", render_activations(batteries, subsequence, &alphabet));
+        }
+
+        activated
+            .into_iter()
+            .map(|subsequence| subsequence.value)
+            .sum()
+    } else {
+        find_joltage_in_battery_packs(&packs, |batteries| Ok(solve(batteries)?.value))?
     };
 
     println!("And joltage is.... {}", value);
@@ -91,27 +390,193 @@ fn day_three(part: &ProblemPart) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn day_four(part: &ProblemPart) -> anyhow::Result<()> {
-    let mut grid = read_rolls(Path::new("./data/day-4/long.txt"));
+fn day_four(part: &ProblemPart, args: &Args) -> anyhow::Result<()> {
+    let mut symbols = Symbols::new(
+        &args.empty_symbols,
+        &args.roll_symbols,
+        &args.to_remove_symbols,
+    )?;
+    symbols.trim = !args.keep_whitespace;
 
-    let value = match part {
-        ProblemPart::One => {
-            get_accessable_rolls::<fn(&mut Vec<Vec<_>>, usize, usize)>(&mut grid, None)
+    let path = Path::new("./data/day-4/long.txt");
+    let rule = Rule {
+        neighborhood: args.neighborhood.clone(),
+        threshold: args.threshold,
+        comparison: args.comparison,
+        boundary: args.boundary,
+    };
+
+    if matches!(args.backend, Backend::Bits)
+        && (args.animate || args.heatmap || args.output.is_some())
+    {
+        bail!("--animate, --heatmap and --output don't work with the bits backend");
+    }
+
+    if args.unbounded && !matches!(args.backend, Backend::Sparse) {
+        bail!("--unbounded needs the sparse backend");
+    }
+
+    match args.backend {
+        Backend::Dense => {
+            day_four_warehouse(read_rolls(path, &symbols)?, part, args, &rule, &symbols)
+        }
+        Backend::Sparse => {
+            // Never builds the dense grid
+            let mut sparse = parse_rolls_sparse(&fs::read_to_string(path)?, &symbols)?;
+            if args.unbounded {
+                sparse = sparse.into_unbounded();
+            }
+
+            day_four_warehouse(sparse, part, args, &rule, &symbols)
+        }
+        Backend::Bits => {
+            // Never builds the dense grid either
+            let mut bits = parse_rolls_bits(&fs::read_to_string(path)?, &symbols)?;
+            let value = match part {
+                ProblemPart::One => get_accessable_rolls_bits(&bits, &rule)?,
+                ProblemPart::Two => get_accessable_rolls_with_removal_bits(&mut bits, &rule)?,
+            };
+            println!("Can move {} rolls", value);
+
+            Ok(())
+        }
+    }
+}
+
+/// Day 4 on any backend that keeps every cell around, dense or sparse
+fn day_four_warehouse<W>(
+    mut warehouse: W,
+    part: &ProblemPart,
+    args: &Args,
+    rule: &Rule,
+    symbols: &Symbols,
+) -> anyhow::Result<()>
+where
+    W: Warehouse + Clone + fmt::Display,
+{
+    if args.heatmap {
+        println!(
+            "Neighbor counts:\n{}",
+            render_heatmap(&warehouse, &get_neighbor_counts(&warehouse, rule)?)
+        );
+
+        let statistics = get_roll_statistics(&warehouse, rule)?;
+        println!(
+            "Round each roll is removed in:\n{}",
+            render_heatmap(&warehouse, &statistics.depths)
+        );
+        for (count, rolls) in statistics.histogram.iter().enumerate() {
+            println!("{:>3} neighbors: {} rolls", count, rolls);
+        }
+        println!("Never accessible: {} rolls", statistics.never_accessible);
+    }
+
+    let value = match (part, args.animate) {
+        (ProblemPart::One, false) => {
+            get_accessable_rolls::<W, fn(&mut W, W::Position)>(&mut warehouse, rule, None)?
+        }
+        (ProblemPart::One, true) => {
+            let value = get_accessable_rolls(
+                &mut warehouse,
+                rule,
+                Some(|warehouse: &mut W, position: W::Position| {
+                    warehouse.set_cell(position, Cell::ToRemove);
+                }),
+            )?;
+            println!("{}", warehouse);
+
+            value
+        }
+        (ProblemPart::Two, false) => get_accessable_rolls_with_removal(&mut warehouse, rule)?,
+        (ProblemPart::Two, true) => {
+            println!("Initial state:\n{}", warehouse);
+
+            let rounds = get_removal_rounds(
+                &mut warehouse,
+                rule,
+                Some(|warehouse: &W, round: &Round<W::Position>| {
+                    println!(
+                        "Removed {} rolls, {} remaining:\n{}",
+                        round.removed.len(),
+                        round.remaining,
+                        warehouse
+                    );
+                }),
+            )?;
+
+            rounds.iter().map(|round| round.removed.len() as u32).sum()
         }
-        ProblemPart::Two => get_accessable_rolls_with_removal(&mut grid),
     };
 
     println!("Can move {} rolls", value);
 
+    if let Some(output) = &args.output {
+        fs::write(output, write_rolls(&warehouse, symbols))?;
+    }
+
     Ok(())
 }
 
-fn day_five(part: &ProblemPart) -> anyhow::Result<()> {
-    let (ranges, ids) = read_ids(Path::new("./data/day-5/long.txt"));
+fn day_five(part: &ProblemPart, args: &Args) -> anyhow::Result<()> {
+    let (overlaps, ids) = match &args.ranges {
+        Some(ranges) => read_ids_split(ranges, args.ids.as_deref())?,
+        None => read_ids(Path::new("./data/day-5/long.txt"))?,
+    };
+    let ranges = overlaps.merged();
 
-    let value = match part {
-        ProblemPart::One => find_valid_ids(ids, &ranges),
-        ProblemPart::Two => get_total_fresh(&ranges),
+    if args.overlaps {
+        println!("Deepest overlap: {} ranges", overlaps.max_depth());
+    }
+
+    for id in args.lookup.iter() {
+        let lines = overlaps
+            .containing(*id)
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<String>>();
+        println!(
+            "ID {} is in {} ranges, on lines: {}",
+            id,
+            overlaps.depth(*id),
+            lines.join(", ")
+        );
+    }
+
+    if let Some(path) = &args.compare {
+        let (other, _) = read_ids(path)?;
+        let other = other.merged();
+        println!("Fresh in both: {}", ranges.intersection(other).len());
+        println!("Fresh only here: {}", ranges.difference(other).len());
+        println!(
+            "Fresh only in {}: {}",
+            path.display(),
+            other.difference(ranges).len()
+        );
+        println!("Fresh in either: {}", ranges.union(other).len());
+    }
+
+    if args.gaps
+        && let Some(bounds) = ranges.bounds()
+    {
+        let gaps = ranges.complement(0..=*bounds.end());
+        for gap in &gaps {
+            println!("{}-{}", gap.start(), gap.end());
+        }
+        println!("{} IDs in {} gaps", gaps.len(), gaps.iter().count());
+    }
+
+    if let Some(path) = &args.audit {
+        fs::write(path, render_audit(&audit_ids(&ids, ranges)))?;
+    }
+
+    if let Some(count) = args.benchmark {
+        benchmark_day_five(ranges, count);
+    }
+
+    let value = match (part, args.strategy) {
+        (ProblemPart::One, LookupStrategy::BinarySearch) => find_valid_ids(ids, ranges),
+        (ProblemPart::One, LookupStrategy::MergeJoin) => find_valid_ids_merged(&ids, ranges),
+        (ProblemPart::Two, _) => get_total_fresh(ranges),
     };
 
     println!("Fresh ingredients: {}, ...", value);
@@ -119,6 +584,110 @@ fn day_five(part: &ProblemPart) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Looks up `count` IDs spread over the ranges, and a bit past them, with
+/// both strategies
+fn benchmark_day_five(ranges: &IntervalSet<u128>, count: usize) {
+    let highest = ranges.bounds().map_or(0, |bounds| *bounds.end());
+    // A tenth past the highest fresh ID, so some IDs miss every range
+    let limit = highest.saturating_add(highest / 10).saturating_add(1);
+    let mut rng = XorShift::new(0x9b05688c2b3e6c1f);
+    let ids = (0..count)
+        .map(|_| rng.next_u64() as u128 % limit)
+        .collect::<Vec<u128>>();
+
+    let start = Instant::now();
+    let binary_search = find_valid_ids(ids.clone(), ranges);
+    println!(
+        "Binary search: {} fresh in {:?}",
+        binary_search,
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let merge_join = find_valid_ids_merged(&ids, ranges);
+    println!("Merge join: {} fresh in {:?}", merge_join, start.elapsed());
+}
+
+fn index(action: &IndexAction) -> anyhow::Result<()> {
+    match action {
+        IndexAction::Build { input, output } => {
+            let (overlaps, _) = read_ids(input)?;
+            let bytes = encode_index(overlaps.merged());
+            fs::write(output, &bytes)?;
+
+            println!(
+                "Wrote {} ranges in {} bytes to {}",
+                overlaps.merged().iter().count(),
+                bytes.len(),
+                output.display()
+            );
+        }
+        IndexAction::Query { index, ids } => {
+            let file = File::open(index)
+                .with_context(|| format!("Couldn't open index {}", index.display()))?;
+            let mut reader = IndexReader::new(file)?;
+            println!(
+                "Index has {} ranges, with {} fresh IDs",
+                reader.ranges(),
+                reader.covered()
+            );
+
+            for id in ids {
+                let status = if reader.contains(*id)? {
+                    "fresh"
+                } else {
+                    "spoiled"
+                };
+                println!("{}: {}", id, status);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn repl(input: &Path) -> anyhow::Result<()> {
+    let (overlaps, _) = read_ids(input)?;
+    let mut ranges = overlaps.merged().clone();
+
+    println!(
+        "Loaded {} ranges. Commands: check <id>, add <a>-<b>, remove <a>-<b>, count, gaps, dump, quit",
+        ranges.iter().count()
+    );
+
+    print!("> ");
+    io::stdout().flush()?;
+    for line in io::stdin().lines() {
+        match line?.trim() {
+            "" => (),
+            "quit" | "exit" => break,
+            line => match run_command(&mut ranges, line) {
+                Ok(output) => println!("{}", output),
+                Err(error) => println!("Error: {:#}", error),
+            },
+        }
+
+        print!("> ");
+        io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+fn run_day(day: u16, args: &Args) -> anyhow::Result<()> {
+    match day {
+        0 => {
+            bail!("I said 1-index! jeez")
+        }
+        1 => day_one(&args.part),
+        2 => day_two(&args.part),
+        3 => day_three(&args.part, args),
+        4 => day_four(&args.part, args),
+        5 => day_five(&args.part, args),
+        _ => bail!("I'm working on it... heheheh"),
+    }
+}
+
 fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -131,16 +700,10 @@ fn main() -> anyhow::Result<()> {
 
     let start = now();
 
-    let result = match args.day {
-        0 => {
-            bail!("I said 1-index! jeez")
-        }
-        1 => day_one(&args.part),
-        2 => day_two(&args.part),
-        3 => day_three(&args.part),
-        4 => day_four(&args.part),
-        5 => day_five(&args.part),
-        _ => bail!("I'm working on it... heheheh"),
+    let result = match (&args.command, args.day) {
+        (Some(Command::Index { action }), _) => index(action),
+        (Some(Command::Repl { input }), _) => repl(input),
+        (None, day) => run_day(day, &args),
     };
 
     let end = now();
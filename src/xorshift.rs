@@ -0,0 +1,25 @@
+//! Tiny xorshift generator, so made up inputs are random-looking but
+//! reproducible from their seed.
+
+/// Marsaglia's 64 bit xorshift. Fast and good enough to make up inputs, not
+/// for anything that needs real randomness.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// The seed can't be 0, since every number after it would be 0 too
+    pub fn new(seed: u64) -> Self {
+        assert!(seed != 0, "Seed must not be 0");
+
+        XorShift { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}